@@ -63,7 +63,6 @@ export default defineConfig((ctx) => {
 			// publicPath: '/',
 			// analyze: true,
 			env: {
-				GITHUB_API_TOKEN: process.env.GITHUB_API_TOKEN,
 				PINIA_STORE_PATH: process.env.PINIA_STORE_PATH,
 			},
 			// rawDefine: {}
@@ -162,7 +161,7 @@ export default defineConfig((ctx) => {
 			// directives: [],
 
 			// Quasar plugins
-			plugins: ["Notify", "Screen"],
+			plugins: ["Dialog", "Notify", "Screen"],
 		},
 
 		animations: "all", // --- includes all animations
@@ -6,6 +6,8 @@ import type { LogEntry } from "src/types/installation";
 import { parseFirmwareFilename } from "src/utils/filename-parsing";
 
 let unlisten: null | (() => void) = null;
+let unlistenBatch: null | (() => void) = null;
+let unlistenRestore: null | (() => void) = null;
 let listenersStarted = false;
 
 export const useDeviceStateController = () => {
@@ -18,6 +20,11 @@ export const useDeviceStateController = () => {
 
     const state = payload.state;
 
+    installationStore.offline = payload.offline;
+
+    serialStore.devices = payload.devices;
+    serialStore.selectedDeviceTag = payload.selected_device;
+
     switch (state.kind) {
       case "Disconnected": {
         serialStore.device = null;
@@ -35,8 +42,16 @@ export const useDeviceStateController = () => {
         serialStore.device = state.device;
         installationStore.downloadStatus = state.status;
 
+        if (state.status.state === "Stopped") {
+          installationStore.uploadLog.push({
+            line: state.status.log ?? "Download stopped",
+            state: "Stopped",
+          });
+          break;
+        }
+
         installationStore.uploadLog.push({
-          line: `Downloading... ${(installationStore.downloadProgress * 100).toFixed(2)}%`,
+          line: `${state.status.log ?? "Downloading"}... ${(installationStore.downloadProgress * 100).toFixed(2)}%`,
           state: "Starting",
         });
         break;
@@ -87,6 +102,18 @@ export const useDeviceStateController = () => {
       handleDeviceStateUpdate,
     );
 
+    unlistenBatch = await registerIpcEventListener(
+      "batch-flash-update",
+      (batch) => (installationStore.batch = batch),
+    );
+
+    unlistenRestore = await registerIpcEventListener(
+      "restore-selected",
+      ({ path, version }) => {
+        installationStore.selectedFirmware = { path, source: "remote", version };
+      },
+    );
+
     listenersStarted = true;
   }
 
@@ -105,10 +132,13 @@ export const useDeviceStateController = () => {
     import.meta.hot.dispose(() => {
       try {
         unlisten?.();
+        unlistenBatch?.();
+        unlistenRestore?.();
       } catch {
         /* noop */
       }
       unlisten = null;
+      unlistenBatch = null;
       listenersStarted = false;
     });
   }
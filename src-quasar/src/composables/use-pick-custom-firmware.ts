@@ -10,8 +10,9 @@ export const usePickCustomFirmware = () => {
     const selected = await open({
       multiple: false,
       directory: false,
+      // Dialogs only match the last extension, so the backend rejects a `.gz` that isn't a tarball
       filters: [
-        { name: 'Firmware', extensions: ['hex', 'zip'] },
+        { name: 'Firmware', extensions: ['hex', 'zip', 'gz', 'tgz', 'xz', 'txz'] },
       ],
     });
 
@@ -1,8 +1,9 @@
-import { emitTo } from "@tauri-apps/api/event";
+import { invoke } from "@tauri-apps/api/core";
 import { sep } from "@tauri-apps/api/path";
 import { acceptHMRUpdate, defineStore, storeToRefs } from "pinia";
+import { Notify } from "quasar";
 import { useFirmwareStore } from "src/stores/firmware";
-import type { DownloadStatus, Firmware, UploadState } from "src/types";
+import type { BatchStatus, DownloadStatus, Firmware, UploadState } from "src/types";
 import type { LogEntry } from "src/types/installation";
 
 export type FirmwareSource = "local" | "remote";
@@ -14,8 +15,11 @@ export type SelectedFirmware = {
 };
 
 type InstallationStoreState = {
+  // The latest batch flash, until it is dismissed
+  batch: BatchStatus | null;
   cachedLocalFirmware: SelectedFirmware | null;
   downloadStatus: Omit<DownloadStatus, "log">;
+  offline: boolean;
   selectedFirmware: SelectedFirmware | null;
   uploadLog: LogEntry[];
   uploadState: UploadState;
@@ -47,12 +51,14 @@ export const useInstallationStore = defineStore<
   }
 >("installation", {
   state: () => ({
+    batch: null,
     cachedLocalFirmware: null,
     downloadStatus: {
       bytes_downloaded: 0,
       size: 0,
       state: "Stopped",
     },
+    offline: false,
     selectedFirmware: null,
     uploadLog: [],
     uploadState: "Stopped",
@@ -63,9 +69,14 @@ export const useInstallationStore = defineStore<
       : state.downloadStatus.bytes_downloaded / state.downloadStatus.size,
     isFlashing: (state) =>
       state.downloadStatus.state !== "Stopped" ||
-      state.uploadState !== "Stopped",
+      state.uploadState !== "Stopped" ||
+      (state.batch !== null && state.batch.stage !== "Finished"),
     installationStatus: (state) => {
-      if (state.downloadStatus.state !== 'Stopped' || state.uploadState !== 'Stopped') {
+      if (
+        state.downloadStatus.state !== 'Stopped' ||
+        state.uploadState !== 'Stopped' ||
+        (state.batch !== null && state.batch.stage !== 'Finished')
+      ) {
         return 'uploading';
       }
 
@@ -94,7 +105,8 @@ export const useInstallationStore = defineStore<
         return path;
       }
 
-      return `https://api.github.com/repos/Dirtywave/M8Firmware/contents/${path}`;
+      // Remote versions are resolved against the backend's firmware catalog
+      return '';
     },
   },
   actions: {
@@ -110,9 +122,14 @@ export const useInstallationStore = defineStore<
 
       const resolvedPath = this.selectedFirmwareDownloadLink;
 
-      console.log('emitting version-selected', { path: resolvedPath, version });
+      try {
+        // Resolves remote versions against the catalog before anything can be flashed
+        await invoke('select_version', { path: resolvedPath, version });
+      } catch (e) {
+        this.selectedFirmware = null;
 
-      await emitTo("main", "version-selected", { path: resolvedPath, version });
+        Notify.create({ type: 'negative', message: `Unable to select ${version}: ${String(e)}` });
+      }
     },
   },
   tauri: {
@@ -3,6 +3,9 @@ import type { Device } from 'src/types';
 
 type SerialPortInfoStoreState = {
 	device: Device | null;
+	// Every connected board, ordered by tag
+	devices: Device[];
+	selectedDeviceTag: string | null;
 };
 
 export const useSerialPortInfoStore = defineStore<
@@ -14,6 +17,7 @@ export const useSerialPortInfoStore = defineStore<
 >('serial-port-info', {
 	state: () => ({
 		device: null,
+		devices: [],
 		selectedDeviceTag: null,
 	}),
 	getters: {
@@ -1,3 +1,5 @@
+import type { BatchStatus } from './firmware';
+
 export type DownloadState = 'Stopped' | 'Starting' | 'Downloading' | 'Complete' | 'Error';
 
 export type UploadState = 'Stopped' | 'Initializing' | 'Starting' | 'Uploading' | 'Finalizing' | 'Error';
@@ -13,6 +15,9 @@ export type DeviceAction = 'add' | 'change' | 'miss' | 'remove';
 
 export type DeviceType = 'HEADLESS' | 'MODEL01' | 'MODEL02' | 'UNKNOWN';
 
+// Running the M8 firmware, waiting in the Teensy bootloader, or left without firmware
+export type DeviceMode = 'Running' | 'Bootloader' | 'Blank';
+
 export type TyCmdListEntry = {
 	action: DeviceAction;
 	capabilities: Capability[];
@@ -27,6 +32,7 @@ export type TyCmdListEntry = {
 export type Device = {
 	action_history: DeviceAction[];
 	device_type: DeviceType;
+	mode: DeviceMode;
 	ty_cmd_info: TyCmdListEntry;
 	updated_at: number;
 };
@@ -88,7 +94,7 @@ export type UploadStatus = OptionalLog & {
 // export const isFlashingUploadStatus = (flashingStatus: FlashingStatus): flashingStatus is FlashingUploadStatus =>
 // 	flashingStatus.cycle === 'Uploading';
 
-export type IpcEvent = 'device-state-update';// 'flashing-status' | 'serial-watch-update';
+export type IpcEvent = 'batch-flash-update' | 'device-state-update' | 'restore-selected';// 'flashing-status' | 'serial-watch-update';
 
 type PayloadWrapper<
 	R extends {
@@ -96,6 +102,9 @@ type PayloadWrapper<
 	},
 > = R;
 
+// How connected boards are found
+export type DeviceProviderKind = "Tycmd" | "SerialPort";
+
 export type DeviceState =
   | { kind: "Disconnected" }
   | { kind: "Ready"; device: Device }
@@ -104,11 +113,22 @@ export type DeviceState =
   | { kind: "Error"; device: Device; message: string };
 
 export type DeviceStateUpdate = {
+  devices: Device[];
+  offline: boolean;
+  selected_device: string | null;
   state: DeviceState;
 };
 
+// The firmware a restore selected in place of the user's choice
+export type RestoreSelection = {
+  path: string;
+  version: string;
+};
+
 export type IpcEventPayloads = PayloadWrapper<{
+  'batch-flash-update': BatchStatus
   'device-state-update': DeviceStateUpdate
+  'restore-selected': RestoreSelection
 	// 'flashing-status': FlashingStatus;
 	// 'serial-watch-update': SerialWatchUpdate;
 }>;
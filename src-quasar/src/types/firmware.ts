@@ -1,3 +1,5 @@
+import type { DeviceType } from "./events";
+
 export type ChangelogEntry = {
 	description: string;
 	type: "change" | "fix" | "improved" | "new";
@@ -12,9 +14,76 @@ export type ChangelogSection = {
 
 export type FirmwareMetadata = {
 	path: string;
+	size?: number;
+	version: string;
+};
+
+export type FirmwareRelease = {
+	changelog: ChangelogSection[];
+	date: string | null;
+	download_url: string | null;
+	path: string;
+	sha: string | null;
+	size: number;
 	version: string;
 };
 
+export type FirmwareVariant = {
+	model: DeviceType;
+	version: string | null;
+};
+
+export type InspectedEntry = {
+	embedded_version: string | null;
+	error: string | null;
+	is_hex: boolean;
+	name: string;
+	selected: boolean;
+	size: number;
+	variant: FirmwareVariant | null;
+	warnings: string[];
+};
+
+export type ArchiveInspection = {
+	entries: InspectedEntry[];
+	error: string | null;
+};
+
+export type FlashStage = "Downloading" | "Uploading";
+
+export type FlashJob = {
+	device_serial: string | null;
+	path: string | null;
+	stage: FlashStage;
+	started_at: number;
+	version: string | null;
+};
+
+export type BatchStage = "Downloading" | "Uploading" | "Finished";
+
+export type DeviceOutcome = "Pending" | "Flashing" | "Succeeded" | "Failed";
+
+export type BatchDevice = {
+	device_type: DeviceType;
+	error: string | null;
+	log: string | null;
+	outcome: DeviceOutcome;
+	serial: string;
+	tag: string;
+};
+
+export type BatchSummary = {
+	failed: string[];
+	succeeded: string[];
+};
+
+export type BatchStatus = {
+	devices: BatchDevice[];
+	error: string | null;
+	stage: BatchStage;
+	summary: BatchSummary | null;
+};
+
 export type Firmware = FirmwareMetadata & {
 	changelog?: ChangelogSection[];
 	date?: string;
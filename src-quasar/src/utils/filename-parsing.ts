@@ -1,21 +1,22 @@
-export type Model = 'MODEL:01' | 'MODEL:02';
+export type Model = 'MODEL:01' | 'MODEL:02' | 'HEADLESS';
 
-export const isModelString = (input: string): input is Model => input === 'MODEL:01' || input === 'MODEL:02';
+export const isModelString = (input: string): input is Model =>
+  input === 'MODEL:01' || input === 'MODEL:02' || input === 'HEADLESS';
 
 export type FirmwareInfo  = {
   version: string;
-  model: 'MODEL:01' | 'MODEL:02';
+  model: Model;
 }
 
 /**
  * Parses a firmware filename into a strongly-typed object.
- * @param filename The firmware filename (e.g. "M8_V6_2_0_BETA8A_MODEL02.hex")
+ * @param filename The firmware filename (e.g. "M8_V6_2_0_BETA8A_MODEL02.hex" or "M8_V4_0_1_HEADLESS.hex")
  * @returns An object with `version` and `model` fields, or null if parsing fails.
  */
 export const parseFirmwareFilename = (filename: string): FirmwareInfo | null => {
   const base = filename.replace(/\.hex$/i, '');
 
-  const regex = /^M8_V(\d+)_(\d+)_(\d+)(?:_BETA(\d+)([A-Z])?)?(?:([A-Z]))?(?:_MODEL(\d+))?$/i;
+  const regex = /^M8_V(\d+)_(\d+)_(\d+)(?:_BETA(\d+)([A-Z])?)?(?:([A-Z]))?(?:_MODEL(\d+)|(_HEADLESS))?$/i;
 
   const match = base.match(regex);
 
@@ -32,6 +33,7 @@ export const parseFirmwareFilename = (filename: string): FirmwareInfo | null =>
     betaLetter,
     patchLetter,
     modelNum,
+    headless,
   ] = match;
 
   let version = `${major}.${minor}.${patch}`;
@@ -42,7 +44,7 @@ export const parseFirmwareFilename = (filename: string): FirmwareInfo | null =>
     version += patchLetter;
   }
 
-  const model = `MODEL:${modelNum ?? '01'}`;
+  const model = headless ? 'HEADLESS' : `MODEL:${modelNum ?? '01'}`;
 
   if (!isModelString(model)) {
     return null;
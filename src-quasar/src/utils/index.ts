@@ -2,7 +2,6 @@ export * from './changelog';
 export * from './events';
 export * from './serial';
 export * from './text';
-export * from './url';
 
 type RGB = [number, number, number];
 
@@ -61,16 +61,6 @@ macro_rules! impl_event {
     };
 }
 
-pub struct VersionSelected;
-
-#[derive(Deserialize, Debug)]
-pub struct VersionSelectedPayload {
-    pub path: String,
-    pub version: Option<String>,
-}
-
-impl_event!(VersionSelected, VersionSelectedPayload, "version-selected");
-
 pub struct FrontendLoaded;
 
 impl_event!(FrontendLoaded, (), "frontend-loaded");
@@ -88,6 +78,10 @@ impl_event!(
     "start-firmware-download"
 );
 
+pub struct CancelFirmwareDownload;
+
+impl_event!(CancelFirmwareDownload, (), "cancel-firmware-download");
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum DownloadState {
     Stopped,
@@ -1,18 +1,16 @@
-use anyhow::{Error, Result};
+use anyhow::Result;
 use async_trait::async_trait;
-use futures_util::StreamExt;
-use reqwest::header::USER_AGENT;
 use serde::{Deserialize, Serialize};
 use std::{
-    io::Write,
+    future::Future,
     path::{Path, PathBuf},
     sync::Arc,
 };
 
-use tauri::{http::HeaderMap, AppHandle, Emitter, Manager};
+use tauri::{AppHandle, Emitter, Manager};
 use tauri_plugin_fs::{FilePath, FsExt, OpenOptions};
 use tauri_plugin_shell::{process::CommandEvent, ShellExt};
-use zip::ZipArchive;
+use tokio::sync::mpsc;
 
 use crate::{
     events::frontend_events::{
@@ -26,10 +24,43 @@ use crate::{
     state::{AppState, AppStateData},
 };
 
+pub mod archive;
+pub mod batch;
+pub mod board;
+pub mod cache;
+pub mod catalog;
+pub mod changelog;
+pub mod download;
+pub mod github;
+pub mod inspect;
+pub mod integrity;
+pub mod intel_hex;
+pub mod prefetch;
+pub mod recovery;
+pub mod restore;
+pub mod sources;
+pub mod space;
+pub mod storage;
+pub mod variant;
+pub mod version;
+
+use cache::FirmwareCache;
+use download::{DownloadError, DownloadManager, DownloadProgress, DownloadRequest};
+use integrity::ExpectedArchive;
+use recovery::{FlashJob, FlashStage};
+use space::SpaceNeeded;
+use variant::FirmwareImage;
+use version::M8Version;
+
+// Subdirectory of the system temp directory that downloads are written to
+const DOWNLOADS_DIR_NAME: &str = "m8-firmware-downloads";
+
 const KNOWN_M8_DESCRIPTIONS: [&str; 3] = ["HalfKay", "M8", "Teensyduino RawHID"];
 
 const RESOURCE_BUSY_SUBSTRING: &str = "failed: Resource busy";
 
+const DEVICE_BUSY_MESSAGE: &str = "Device busy. Using remote display?";
+
 const SENDING_RESET_COMMAND_SUBSTRING: &str = "Sending reset command (with RTC)";
 
 // TODO: Address this edge case
@@ -39,7 +70,7 @@ fn path_to_str(path: &Path) -> String {
     path.to_str().unwrap_or_default().to_owned()
 }
 
-fn is_hex(path: &Path) -> bool {
+pub(crate) fn is_hex(path: &Path) -> bool {
     path.extension().and_then(|ext| ext.to_str()) == Some("hex")
 }
 
@@ -73,8 +104,32 @@ pub enum DeviceType {
 // 	]
 // }
 
+/// What a board is running, told apart by the description it reports.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum DeviceMode {
+    // The M8 firmware
+    #[default]
+    Running,
+    // Waiting in the Teensy bootloader, e.g. after a flash was cut short
+    Bootloader,
+    // Teensyduino's default program, left by a reset or a blank flash
+    Blank,
+}
+
+impl DeviceMode {
+    pub fn of(info: &TyCmdListEntry) -> Self {
+        match info.description.as_str() {
+            "HalfKay" => DeviceMode::Bootloader,
+            "Teensyduino RawHID" => DeviceMode::Blank,
+            _ => DeviceMode::Running,
+        }
+    }
+}
+
+/// The model of a board running the M8 firmware or waiting to be restored; the Teensy
+/// model is reported in either case.
 pub fn determine_device_type(info: &TyCmdListEntry) -> DeviceType {
-    if !info.description.eq("M8") && !info.description.eq("HalfKay") {
+    if !KNOWN_M8_DESCRIPTIONS.contains(&info.description.as_str()) {
         return DeviceType::UNKNOWN;
     }
 
@@ -139,10 +194,19 @@ pub struct ConnectedDevice {
     // remove 	This board has been missing for some time, consider it removed
     pub action_history: Vec<String>,
     pub device_type: DeviceType,
+    pub mode: DeviceMode,
     pub ty_cmd_info: TyCmdListEntry,
     pub updated_at: i64,
 }
 
+impl ConnectedDevice {
+    /// Whether the board lacks working M8 firmware and should only be flashed by a
+    /// confirmed restore.
+    pub fn needs_recovery(&self) -> bool {
+        self.mode != DeviceMode::Running
+    }
+}
+
 pub type ConnectedDeviceList = Vec<ConnectedDevice>;
 
 #[derive(Debug, thiserror::Error)]
@@ -245,7 +309,8 @@ fn setup_temp_download_dir(app_handle: &AppHandle) -> Result<()> {
 
                 with_extension.set_extension("app");
 
-                Ok(temp_dir)
+                // Kept apart from other apps' files so leftovers can be cleaned up safely
+                Ok(temp_dir.join(DOWNLOADS_DIR_NAME))
             }
             Err(error) => Err(anyhow::Error::new(error)),
         },
@@ -260,46 +325,169 @@ fn setup_temp_download_dir(app_handle: &AppHandle) -> Result<()> {
 async fn extract_firmware_to_cache(
     app_handle: &AppHandle,
     file_path: FilePath,
-    filter: Option<fn(&Path) -> bool>, // Optional filter for files to extract
-) -> Result<Vec<PathBuf>> {
+    filter: fn(&Path) -> bool, // Filter for files to extract
+    source_url: Option<String>,
+    version: Option<M8Version>,
+) -> Result<Vec<FirmwareImage>> {
+    let mut cache = storage::lock_cache(app_handle).await?;
+
+    extract_into_cache(
+        app_handle, &mut cache, file_path, filter, source_url, version,
+    )
+    .await
+}
+
+/// Like `extract_firmware_to_cache`, for callers already holding the cache lock.
+async fn extract_into_cache(
+    app_handle: &AppHandle,
+    cache: &mut FirmwareCache,
+    file_path: FilePath,
+    filter: fn(&Path) -> bool,
+    source_url: Option<String>,
+    version: Option<M8Version>,
+) -> Result<Vec<FirmwareImage>> {
     log::info!("In extract_firmware_to_cache");
     log::info!("path is {}", file_path);
 
-    let mut cache_paths: Vec<PathBuf> = Vec::new();
-
     let file = FsExt::fs(app_handle)
         .open::<FilePath>(file_path, OpenOptions::new().read(true).to_owned())?;
 
-    let mut archive = ZipArchive::new(file)?;
+    space::preflight(&[SpaceNeeded {
+        bytes: space::extracted_estimate(file.metadata()?.len()),
+        dir: cache.root().to_path_buf(),
+    }])?;
 
-    for i in 0..archive.len() {
-        let mut archive_file = archive.by_index(i)?;
-        let path = archive_file
-            .enclosed_name()
-            .ok_or_else(|| anyhow::Error::msg("Invalid path"))?;
+    let images = cache.insert_archive(file, filter, source_url, version)?;
 
-        // Apply the filter if provided
-        if let Some(filter_fn) = filter {
-            if !filter_fn(&path) {
-                continue;
-            }
+    // The new entry is the most recently used, so it survives eviction
+    if let Err(error) = storage::evict_by_policy(app_handle, cache).await {
+        log::warn!("Unable to trim firmware cache: {}", error);
+    }
+
+    Ok(images)
+}
+
+/// Checks there is room for an archive of `size` bytes at `destination`, less anything
+/// already downloaded, and for its images in the cache.
+async fn preflight_download(app_handle: &AppHandle, destination: &Path, size: u64) -> Result<()> {
+    let downloaded = std::fs::metadata(download::partial_path(destination))
+        .map(|meta| meta.len())
+        .unwrap_or(0);
+
+    let mut needs = vec![SpaceNeeded {
+        bytes: size.saturating_sub(downloaded),
+        dir: destination.parent().unwrap_or(destination).to_owned(),
+    }];
+
+    match storage::cache_root(app_handle).await {
+        Ok(dir) => needs.push(SpaceNeeded {
+            bytes: space::extracted_estimate(size),
+            dir,
+        }),
+        Err(error) => log::warn!("Not checking space for the cache: {}", error),
+    }
+
+    space::preflight(&needs)?;
+
+    Ok(())
+}
+
+/// Downloads `url` to `destination` from the first configured mirror that serves it,
+/// then checks it against the published hash and size. `on_progress` is given the name
+/// of the mirror in use.
+async fn download_verified<F, Fut>(
+    app_handle: &AppHandle,
+    url: &str,
+    destination: PathBuf,
+    expected: Option<&ExpectedArchive>,
+    mut on_progress: F,
+) -> Result<PathBuf>
+where
+    F: FnMut(String, DownloadProgress) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let state = app_handle.state::<AppState>();
+    let state_guard = state.lock().await;
+
+    let mirrors = state_guard.sources.mirrors_for(url);
+
+    let token = state_guard.github_token.clone();
+
+    drop(state_guard);
+
+    let downloads = app_handle.state::<DownloadManager>();
+
+    // Fail before writing anything if the archive can't fit
+    let size = match (expected, mirrors.first()) {
+        (Some(expected), _) => Some(expected.size),
+        (None, Some((mirror, url))) => {
+            let request = DownloadRequest {
+                destination: destination.clone(),
+                headers: mirror.headers("raw", token.as_deref())?,
+                url: url.clone(),
+            };
+
+            downloads
+                .content_length(&request)
+                .await
+                .inspect_err(|error| log::warn!("Unable to get the size of {}: {}", url, error))
+                .ok()
+                .flatten()
         }
+        (None, None) => None,
+    };
 
-        let state = app_handle.state::<AppState>();
-        let state_guard = state.lock().await;
+    if let Some(size) = size {
+        preflight_download(app_handle, &destination, size).await?;
+    }
+
+    let mut last_error = None;
 
-        if let Some(dir) = &state_guard.cache_dir {
-            let cache_path = Path::new(dir.as_path().unwrap()).join(path.file_name().unwrap());
-            let mut cache_file = FsExt::fs(app_handle).open::<FilePath>(
-                FilePath::Path(cache_path.clone()),
-                OpenOptions::new().create(true).write(true).to_owned(),
-            )?;
-            std::io::copy(&mut archive_file, &mut cache_file)?;
-            cache_paths.push(cache_path);
+    for (mirror, url) in mirrors {
+        log::info!("Downloading {} from {}", url, mirror.name);
+
+        let request = DownloadRequest {
+            destination: destination.clone(),
+            headers: mirror.headers("raw", token.as_deref())?,
+            url,
+        };
+
+        let result = downloads
+            .download(request, |progress| {
+                on_progress(mirror.name.clone(), progress)
+            })
+            .await;
+
+        match result {
+            Ok(path) => {
+                if let Some(expected) = expected {
+                    integrity::verify_download(&path, expected)?;
+                }
+
+                return Ok(path);
+            }
+            Err(DownloadError::Cancelled) => return Err(DownloadError::Cancelled.into()),
+            Err(error) => {
+                log::warn!("Download from {} failed: {}", mirror.name, error);
+
+                // Another mirror's bytes can't be resumed onto this one's
+                let partial = download::partial_path(&destination);
+
+                if let Err(error) = std::fs::remove_file(&partial) {
+                    if error.kind() != std::io::ErrorKind::NotFound {
+                        log::warn!("Unable to delete {:?}: {}", partial, error);
+                    }
+                }
+
+                last_error = Some(error);
+            }
         }
     }
 
-    Ok(cache_paths)
+    Err(last_error.map_or_else(
+        || anyhow::Error::msg("No firmware sources configured"),
+        anyhow::Error::from,
+    ))
 }
 
 async fn fetch_archive(app_handle: &AppHandle, source: ArchiveSource) -> Result<FilePath> {
@@ -313,9 +501,7 @@ async fn fetch_archive(app_handle: &AppHandle, source: ArchiveSource) -> Result<
 
             let state = app_handle.state::<AppState>();
 
-            let mut state_guard = state.lock().await;
-
-            log::info!("state lock guard acquired");
+            let state_guard = state.lock().await;
 
             let temp_dir = state_guard
                 .temp_dir
@@ -324,114 +510,56 @@ async fn fetch_archive(app_handle: &AppHandle, source: ArchiveSource) -> Result<
 
             log::info!("temp directory is {}", temp_dir);
 
-            let mut headers = HeaderMap::new();
-
-            headers.insert("Accept", "application/vnd.github.raw+json".parse()?);
-            headers.insert(USER_AGENT, "com.dirtywave.updater".parse()?);
-            headers.insert("X-GitHub-Api-Version", "2022-11-28".parse()?);
-
-            let client = reqwest::Client::new();
-
-            let head = client.head(&url).headers(headers.clone()).send().await;
-
-            state_guard.size = match head {
-                Ok(response) => {
-                    if let Some(content_length) = response.headers().get("content-length") {
-                        if let Ok(size_str) = content_length.to_str() {
-                            if let Ok(size) = size_str.parse::<u64>() {
-                                log::info!(
-                                    "File size: {} bytes ({:.2} MB)",
-                                    size,
-                                    size as f64 / 1_048_576.0
-                                );
-                                size
-                            } else {
-                                log::warn!("Could not parse content-length: {}", size_str);
-                                0
-                            }
-                        } else {
-                            0
-                        }
-                    } else {
-                        log::warn!("No content-length header found");
-                        0
-                    }
-                }
-                Err(e) => {
-                    log::error!("HEAD request failed: {}", e);
-                    0
-                }
-            };
+            let expected = state_guard.expected_archive.clone();
 
-            let size = state_guard.size;
-
-            let version = state_guard.version.clone();
+            let version = state_guard
+                .version
+                .as_ref()
+                .map(M8Version::file_stem)
+                .unwrap_or_default();
 
             drop(state_guard);
 
-            let temp_path = FilePath::Path(
-                Path::new(temp_dir.as_path().unwrap()).join(format!("{}.zip", version)),
-            );
-
-            log::info!("temp path is {}", temp_path);
-
-            let mut temp_file = FsExt::fs(app_handle).open::<FilePath>(
-                temp_path.clone(),
-                OpenOptions::new().create(true).write(true).to_owned(),
-            )?;
-
-            let stream = client.get(url).headers(headers).send().await;
-
-            if let Err(error) = stream {
-                log::info!("error downloading from GitHub/ opening stream");
-                return Err(anyhow::Error::new(error));
-            }
-
-            let mut stream = stream.unwrap().bytes_stream();
-
-            let mut bytes_downloaded: u32 = 0;
-
-            let mut last_progress_update = std::time::Instant::now();
+            let destination =
+                Path::new(temp_dir.as_path().unwrap()).join(format!("{}.zip", version));
 
-            const PROGRESS_UPDATE_INTERVAL: std::time::Duration =
-                std::time::Duration::from_millis(100);
+            log::info!("temp path is {:?}", destination);
 
-            while let Some(chunk) = stream.next().await {
-                let bytes = chunk?;
+            let shared_state = state.inner();
 
-                temp_file.write_all(&bytes)?;
+            let path = download_verified(
+                app_handle,
+                &url,
+                destination,
+                expected.as_ref(),
+                |mirror, progress| async move {
+                    log::info!("Surfacing download progress {}", progress.bytes_downloaded);
 
-                let chunk_size = u32::try_from(bytes.len())?;
+                    let mut state_guard = shared_state.lock().await;
 
-                bytes_downloaded += chunk_size;
-
-                let now = std::time::Instant::now();
-                if now.duration_since(last_progress_update) >= PROGRESS_UPDATE_INTERVAL {
-                    log::info!("Surfacing download progress {}", bytes_downloaded);
-
-                    let mut state_guard = state.lock().await;
+                    state_guard.size = progress.size;
 
                     state_guard.flashing = Some(FlashingStatus::Downloading(DownloadStatus {
-                        bytes_downloaded,
-                        log: None,
-                        size,
+                        bytes_downloaded: u32::try_from(progress.bytes_downloaded)
+                            .unwrap_or(u32::MAX),
+                        log: Some(format!("Downloading from {}", mirror)),
+                        size: progress.size,
                         state: DownloadState::Downloading,
                     }));
 
                     let _ = state_guard.emit_device_state_update(app_handle);
+                },
+            )
+            .await?;
 
-                    drop(state_guard);
-
-                    last_progress_update = now;
-                }
-            }
-
-            log::info!("Download complete, returning OK {}", temp_path);
+            log::info!("Download complete, returning OK {:?}", path);
 
             let mut state_guard = state.lock().await;
 
+            let size = state_guard.size;
+
             state_guard.flashing = Some(FlashingStatus::Downloading(DownloadStatus {
-                bytes_downloaded,
+                bytes_downloaded: u32::try_from(size).unwrap_or(u32::MAX),
                 log: Some("Download complete".to_string()),
                 size,
                 state: DownloadState::Complete,
@@ -441,44 +569,81 @@ async fn fetch_archive(app_handle: &AppHandle, source: ArchiveSource) -> Result<
 
             drop(state_guard);
 
-            Ok(temp_path)
+            Ok(FilePath::Path(path))
         }
     }
 }
 
-pub struct CachePath {
-    pub device_type: DeviceType,
-    pub path: PathBuf,
-}
-
-pub type CachePaths = Vec<CachePath>;
-
-pub async fn download_firmware(app_handle: &AppHandle) -> Result<CachePaths> {
+pub async fn download_firmware(app_handle: &AppHandle) -> Result<Vec<FirmwareImage>> {
     log::info!("In download_firmware");
     let state = app_handle.state::<AppState>();
     let state_guard = state.lock().await;
 
     let source = state_guard.archive_source.clone();
+    let size = state_guard.size;
+    let version = state_guard.version.clone();
 
     drop(state_guard);
 
-    // Support both remote archives and local .zip/.hex files
-    let filter_hex: Option<fn(&Path) -> bool> = Some(is_hex);
-
-    let paths: Vec<PathBuf> = match source {
+    let images = match source {
         ArchiveSource::LocalPath(p) => {
             log::info!("Fetching local path");
-            if p.extension().and_then(|ext| ext.to_str()) == Some("hex") {
-                vec![p]
+            if is_hex(&p) {
+                variant::classify_archive(vec![p])?
             } else {
-                let file_path = FilePath::Path(p);
-                extract_firmware_to_cache(app_handle, file_path, filter_hex).await?
+                extract_firmware_to_cache(app_handle, FilePath::Path(p), is_hex, None, version)
+                    .await?
             }
         }
-        ArchiveSource::RemoteUrl(_) => {
+        ArchiveSource::RemoteUrl(ref url) => {
+            let mirrors = state.lock().await.sources.mirrors_for(url);
+
+            // An archive fetched from any mirror is as good as one from the primary source
+            let cached = {
+                let mut cache = storage::lock_cache(app_handle).await?;
+
+                mirrors
+                    .iter()
+                    .find_map(|(_, mirror_url)| cache.find_source(mirror_url, version.as_ref()))
+            };
+
+            if let Some(images) = cached {
+                log::info!("Using cached firmware for {}", url);
+
+                let mut state_guard = state.lock().await;
+
+                state_guard.flashing = Some(FlashingStatus::Downloading(DownloadStatus {
+                    bytes_downloaded: u32::try_from(size).unwrap_or(u32::MAX),
+                    log: Some("Using cached firmware".to_string()),
+                    size,
+                    state: DownloadState::Complete,
+                }));
+
+                let _ = state_guard.emit_device_state_update(app_handle);
+
+                return Ok(images);
+            }
+
             log::info!("Fetching remote archive");
+            let source_url = url.clone();
             let file_path = fetch_archive(app_handle, source).await?;
-            extract_firmware_to_cache(app_handle, file_path, filter_hex).await?
+            let images = extract_firmware_to_cache(
+                app_handle,
+                file_path.clone(),
+                is_hex,
+                Some(source_url),
+                version,
+            )
+            .await?;
+
+            // Its images are in the cache now, so the archive itself is no longer needed
+            if let Some(path) = file_path.as_path() {
+                if let Err(error) = std::fs::remove_file(path) {
+                    log::warn!("Unable to delete downloaded archive {:?}: {}", path, error);
+                }
+            }
+
+            images
         }
         ArchiveSource::None => {
             log::info!("No ArchiveSource");
@@ -486,26 +651,15 @@ pub async fn download_firmware(app_handle: &AppHandle) -> Result<CachePaths> {
         }
     };
 
-    log::info!("paths: {:?}", paths);
+    log::info!("images: {:?}", images);
 
-    let tagged_paths: CachePaths = paths
-        .iter()
-        .map(|path| CachePath {
-            device_type: if path.to_str().unwrap_or("").contains("MODEL02") {
-                DeviceType::MODEL02
-            } else {
-                DeviceType::MODEL01
-            },
-            path: path.to_path_buf(),
-        })
-        .collect();
-
-    Ok(tagged_paths)
+    Ok(images)
 }
 
 pub fn setup_firmware_store(app_handle: &AppHandle) -> Result<()> {
     load_firmware_info_cache(app_handle)?;
     setup_temp_download_dir(app_handle)?;
+    recovery::recover(app_handle);
 
     Ok(())
 }
@@ -519,14 +673,13 @@ pub fn start_firmware_download_handler(app_handle: Arc<AppHandle>) {
 
         let state_guard = state.lock().await;
 
-        let device = state_guard.device.clone();
+        let device = state_guard.device().cloned();
 
         log::info!("Starting serial probe for device {:#?}", device);
 
         // Interfaces [["Serial", "/dev/cu.usbmodem149089301"]]
         let interfaces = state_guard
-            .device
-            .as_ref()
+            .device()
             .map(|d| d.ty_cmd_info.interfaces.clone())
             .unwrap_or_default();
 
@@ -549,16 +702,80 @@ pub fn start_firmware_download_handler(app_handle: Arc<AppHandle>) {
         log::info!("Starting firmware download");
 
         let state = download_firmware_app_handle.state::<AppState>();
-        let state_guard = state.lock().await;
+        let mut state_guard = state.lock().await;
 
-        let device = state_guard.device.clone();
+        let device = state_guard.device().cloned();
         let version = state_guard.version.clone();
 
+        // A batch owns every board until it finishes, and one single flash at a time
+        if state_guard.is_flashing() {
+            log::warn!("Not flashing: {}", batch::BatchError::Busy);
+
+            state_guard.flashing = Some(FlashingStatus::Uploading(UploadStatus {
+                log: Some(format!("upload@status {}", batch::BatchError::Busy)),
+                state: UploadState::Error,
+            }));
+
+            let _ = state_guard.emit_device_state_update(&app_handle);
+
+            return;
+        }
+
+        // A confirmation covers the one restore it was given for
+        let confirmed_restore = state_guard.confirmed_restore.take();
+
+        if let Some(device) = device.as_ref().filter(|device| {
+            device.needs_recovery() && confirmed_restore.as_ref() != Some(&device.ty_cmd_info.tag)
+        }) {
+            log::warn!("Refusing to flash {:?} without a confirmed restore", device);
+
+            state_guard.flashing = Some(FlashingStatus::Uploading(UploadStatus {
+                log: Some(format!(
+                    "upload@status {}",
+                    restore::unconfirmed_message(&device.ty_cmd_info.tag, &device.device_type)
+                )),
+                state: UploadState::Error,
+            }));
+
+            let _ = state_guard.emit_device_state_update(&app_handle);
+
+            return;
+        }
+
+        let local_path = match &state_guard.archive_source {
+            ArchiveSource::LocalPath(path) => Some(path_to_str(path)),
+            _ => None,
+        };
+
+        // Claims the flash before the lock is released
+        state_guard.flashing = Some(FlashingStatus::Downloading(DownloadStatus {
+            bytes_downloaded: 0,
+            log: Some("Preparing firmware".to_string()),
+            size: 0,
+            state: DownloadState::Starting,
+        }));
+
+        let _ = state_guard.emit_device_state_update(&app_handle);
+
         drop(state_guard);
 
+        recovery::begin(
+            &download_firmware_app_handle,
+            FlashJob {
+                device_serial: device
+                    .as_ref()
+                    .map(|device| device.ty_cmd_info.serial.clone()),
+                path: local_path,
+                stage: FlashStage::Downloading,
+                started_at: chrono::Utc::now().timestamp_millis(),
+                version: version.clone(),
+            },
+        )
+        .await;
+
         let result = if let Some(device) = device {
             match download_firmware(&download_firmware_app_handle.clone()).await {
-                Ok(firmware_paths) => {
+                Ok(firmware_images) => {
                     let mut state_guard: tokio::sync::MutexGuard<'_, AppStateData> =
                         state.lock().await;
 
@@ -571,97 +788,90 @@ pub fn start_firmware_download_handler(app_handle: Arc<AppHandle>) {
 
                     drop(state_guard);
 
-                    match firmware_paths.iter().find(|cache_path| {
-                        let path = cache_path.path.to_str().unwrap_or_default();
-
-                        let device_type = &format!("{:?}", device.device_type);
-
-                        match device.device_type {
-                            DeviceType::MODEL02 => path.contains(device_type),
-                            DeviceType::MODEL01 => {
-                                // Before the introduction of MODEL:02, the firmware files
-                                // did not specify the supported model in their filenames.
-                                path.contains(device_type)
-                                    || (!path.contains(&format!("{:?}", DeviceType::MODEL02))
-                                        && !path.contains(&format!("{:?}", DeviceType::HEADLESS)))
+                    let target =
+                        variant::select_for_device(firmware_images, &device, version.as_ref())
+                            .map_err(|error| error.to_string())
+                            .and_then(|target| {
+                                intel_hex::validate_image(
+                                    target.path(),
+                                    &device.device_type,
+                                    target.image().variant.version.as_ref(),
+                                )
+                                .map(|(image, warnings)| (target, image, warnings))
+                                .map_err(|error| format!("Firmware image rejected: {}", error))
+                            });
+
+                    match target {
+                        Ok((target, image, warnings)) => {
+                            let embedded = image
+                                .embedded_version()
+                                .map(|version| version.to_string())
+                                .unwrap_or_else(|| "none".to_string());
+
+                            let mut state_guard = state.lock().await;
+
+                            for warning in warnings {
+                                state_guard.flashing =
+                                    Some(FlashingStatus::Uploading(UploadStatus {
+                                        log: Some(format!("upload@status Warning: {}", warning)),
+                                        state: UploadState::Starting,
+                                    }));
+
+                                let _ = state_guard.emit_device_state_update(&app_handle);
                             }
-                            DeviceType::HEADLESS | DeviceType::UNKNOWN => false,
-                        }
-                    }) {
-                        Some(cache_path) => {
-                            let sidecar = download_firmware_app_handle
-                                .shell()
-                                .sidecar("tycmd")
-                                .unwrap()
-                                .set_raw_out(true)
-                                .args([
-                                    "upload",
-                                    cache_path.path.to_str().unwrap_or_default(),
-                                    "--board",
-                                    &device.ty_cmd_info.tag,
-                                ]);
-
-                            let (mut rx, _child) = sidecar.spawn().expect("Failed to spawn tycmd");
-
-                            let mut error: Option<Error> = None;
-
-                            while let Some(event) = rx.recv().await {
-                                if let CommandEvent::Stdout(line) = event.clone() {
-                                    let output = String::from_utf8(line).unwrap();
-                                    log::info!("{}", output);
-
-                                    let state = download_firmware_app_handle.state::<AppState>();
-                                    let mut state_guard = state.lock().await;
 
-                                    let next_state =
-                                        if output.contains(SENDING_RESET_COMMAND_SUBSTRING) {
-                                            UploadState::Finalizing
-                                        } else {
-                                            UploadState::Uploading
-                                        };
+                            state_guard.flashing = Some(FlashingStatus::Uploading(UploadStatus {
+                                log: Some(format!(
+                                    "upload@status Flashing {} (embedded version: {})",
+                                    target
+                                        .path()
+                                        .file_name()
+                                        .and_then(|name| name.to_str())
+                                        .unwrap_or_default(),
+                                    embedded
+                                )),
+                                state: UploadState::Starting,
+                            }));
 
-                                    state_guard.flashing =
-                                        Some(FlashingStatus::Uploading(UploadStatus {
-                                            log: Some(output.clone()),
-                                            state: next_state,
-                                        }));
+                            let _ = state_guard.emit_device_state_update(&app_handle);
 
-                                    let _ = state_guard.emit_device_state_update(&app_handle);
+                            drop(state_guard);
 
-                                    drop(state_guard);
-                                }
+                            recovery::advance(&download_firmware_app_handle, FlashStage::Uploading);
 
-                                if let CommandEvent::Stderr(line) = event.clone() {
-                                    let output = String::from_utf8(line).unwrap();
-                                    log::info!("{}", output);
+                            let uploader = TyCmdUploader {
+                                app_handle: (*download_firmware_app_handle).clone(),
+                            };
 
-                                    error = Some(anyhow::Error::msg(
-                                        if output.contains(RESOURCE_BUSY_SUBSTRING) {
-                                            "upload@status Device busy. Using remote display?"
-                                                .to_string()
-                                        } else {
-                                            output
-                                        },
-                                    ));
+                            let (progress, mut updates) = mpsc::unbounded_channel();
 
-                                    break;
-                                }
+                            let upload = uploader.upload_firmware(
+                                target.path().to_str().unwrap_or_default(),
+                                target.board_tag(),
+                                Box::new(move |status| {
+                                    let _ = progress.send(status);
+                                }),
+                            );
+
+                            // The uploader holds the only sender, so updates end when it does
+                            let collect = async {
+                                while let Some(status) = updates.recv().await {
+                                    log::info!("{}", status.log.as_deref().unwrap_or_default());
 
-                                if let CommandEvent::Error(line) = event.clone() {
-                                    log::info!("{}", line);
+                                    let mut state_guard = state.lock().await;
 
-                                    error = Some(anyhow::Error::msg(line));
+                                    state_guard.flashing = Some(FlashingStatus::Uploading(status));
 
-                                    break;
+                                    let _ = state_guard.emit_device_state_update(&app_handle);
                                 }
-                            }
+                            };
+
+                            let (uploaded, ()) = tokio::join!(upload, collect);
 
                             log::info!("Done receiving data from tycmd child process");
 
-                            match error {
-                                Some(e) => Err(e),
-                                None => {
-                                    let state = download_firmware_app_handle.state::<AppState>();
+                            match uploaded {
+                                Ok(()) => {
                                     let mut state_guard = state.lock().await;
 
                                     state_guard.flashing = None;
@@ -672,22 +882,42 @@ pub fn start_firmware_download_handler(app_handle: Arc<AppHandle>) {
 
                                     Ok(())
                                 }
+                                Err(error) => {
+                                    Err(anyhow::Error::msg(status_log(&error.to_string())))
+                                }
                             }
                         }
-                        None => Err(anyhow::Error::msg(
-                            format!(
-                                "upload@status No {:?} variant found for version {}",
-                                device.device_type, version
-                            )
-                            .to_string(),
-                        )),
+                        Err(error) => Err(anyhow::Error::msg(format!("upload@status {}", error))),
                     }
                 }
-                // TODO: Handle "Failed to download firmware: invalid Zip archive: Invalid local file header"
-                Err(e) => Err(anyhow::Error::msg(format!(
-                    "upload@status Failed to download firmware: {:?}",
-                    e
-                ))),
+                Err(e) if matches!(e.downcast_ref(), Some(DownloadError::Cancelled)) => {
+                    log::info!("Firmware download cancelled");
+
+                    let mut state_guard = state.lock().await;
+
+                    state_guard.flashing = Some(FlashingStatus::Downloading(DownloadStatus {
+                        bytes_downloaded: 0,
+                        log: Some("Download cancelled".to_string()),
+                        size: 0,
+                        state: DownloadState::Stopped,
+                    }));
+
+                    let _ = state_guard.emit_device_state_update(&app_handle);
+
+                    state_guard.flashing = None;
+
+                    let _ = state_guard.emit_device_state_update(&app_handle);
+
+                    Ok(())
+                }
+                Err(e) => Err(anyhow::Error::msg(
+                    match e.downcast_ref::<integrity::IntegrityError>() {
+                        Some(error) => {
+                            format!("upload@status Download failed integrity check: {}", error)
+                        }
+                        None => format!("upload@status Failed to download firmware: {:?}", e),
+                    },
+                )),
             }
         } else {
             Err(anyhow::Error::msg(
@@ -695,6 +925,8 @@ pub fn start_firmware_download_handler(app_handle: Arc<AppHandle>) {
             ))
         };
 
+        recovery::finish(&download_firmware_app_handle);
+
         if let Err(e) = result {
             let state = download_firmware_app_handle.state::<AppState>();
 
@@ -712,6 +944,16 @@ pub fn start_firmware_download_handler(app_handle: Arc<AppHandle>) {
     });
 }
 
+/// Keeps tycmd's own `upload@<tag>` lines, and marks anything else as a status line so the
+/// frontend shows it.
+fn status_log(message: &str) -> String {
+    if message.trim_start().starts_with("upload@") {
+        message.to_string()
+    } else {
+        format!("upload@status {}", message)
+    }
+}
+
 #[async_trait]
 impl FirmwareUploader for TyCmdUploader {
     async fn upload_firmware(
@@ -724,11 +966,13 @@ impl FirmwareUploader for TyCmdUploader {
             .app_handle
             .shell()
             .sidecar("tycmd")
-            .unwrap()
+            .map_err(|error| anyhow::anyhow!("tycmd is unavailable: {}", error))?
             .set_raw_out(true)
             .args(["upload", firmware_path, "--board", board_tag]);
 
-        let (mut rx, _child) = sidecar.spawn().expect("Failed to spawn tycmd");
+        let (mut rx, _child) = sidecar
+            .spawn()
+            .map_err(|error| anyhow::anyhow!("Failed to spawn tycmd: {}", error))?;
 
         let mut error: Option<anyhow::Error> = None;
 
@@ -750,7 +994,15 @@ impl FirmwareUploader for TyCmdUploader {
                 CommandEvent::Stderr(line) => {
                     let output = String::from_utf8_lossy(&line).to_string();
 
-                    error = Some(anyhow::Error::msg(output));
+                    log::info!("{}", output);
+
+                    error = Some(anyhow::Error::msg(
+                        if output.contains(RESOURCE_BUSY_SUBSTRING) {
+                            DEVICE_BUSY_MESSAGE.to_string()
+                        } else {
+                            output
+                        },
+                    ));
 
                     break;
                 }
@@ -816,6 +1068,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn boards_without_firmware_need_recovery() {
+        let blank = entry("Teensyduino RawHID", "Teensy MicroMod");
+        assert_eq!(determine_device_type(&blank), DeviceType::MODEL02);
+        assert_eq!(DeviceMode::of(&blank), DeviceMode::Blank);
+
+        assert_eq!(
+            DeviceMode::of(&entry("HalfKay", "Teensy 4.1")),
+            DeviceMode::Bootloader
+        );
+        assert_eq!(
+            DeviceMode::of(&entry("M8", "Teensy 4.0")),
+            DeviceMode::Running
+        );
+
+        // Without a model there is nothing to restore it to
+        assert_eq!(
+            determine_device_type(&entry("Teensyduino RawHID", "Teensy")),
+            DeviceType::UNKNOWN
+        );
+    }
+
     #[test]
     fn device_type_resolvers_work() {
         // CommandLineResolver
@@ -0,0 +1,473 @@
+use std::{
+    cell::Cell,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+
+use flate2::read::GzDecoder;
+use xz2::read::XzDecoder;
+use zip::ZipArchive;
+
+// Archives inside an archive are opened this many levels deep, and no further
+const MAX_NESTING: usize = 1;
+
+// Below this much output, small archives are allowed any compression ratio
+const RATIO_FLOOR_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("Unable to read archive: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid zip archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("Archive entry has an unsafe path: {0}")]
+    UnsafePath(String),
+    #[error("Unrecognized archive format")]
+    UnknownFormat,
+    #[error("Archive contains more than one file named {0}")]
+    DuplicateName(String),
+    #[error("Archive contains more than {0} files")]
+    TooManyEntries(usize),
+    #[error("Archive expands to more than {} MiB", .0 / (1024 * 1024))]
+    TooLarge(u64),
+    #[error("Archive is compressed more than {0}:1")]
+    CompressionRatio(u64),
+}
+
+/// Bounds on what reading an archive may expand to, nested archives included.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExtractionLimits {
+    pub max_entries: usize,
+    // Uncompressed bytes read per byte of the outer archive
+    pub max_ratio: u64,
+    pub max_total_bytes: u64,
+}
+
+impl Default for ExtractionLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: 1000,
+            max_ratio: 100,
+            max_total_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// What has been read so far, checked against the limits after every read. Shared by
+/// the meters of every stream read from one archive.
+struct Budget {
+    compressed_bytes: u64,
+    entries: Cell<usize>,
+    limits: ExtractionLimits,
+    total_bytes: Cell<u64>,
+}
+
+impl Budget {
+    fn check(&self) -> Result<(), ArchiveError> {
+        let limits = &self.limits;
+        let total_bytes = self.total_bytes.get();
+
+        if self.entries.get() > limits.max_entries {
+            Err(ArchiveError::TooManyEntries(limits.max_entries))
+        } else if total_bytes > limits.max_total_bytes {
+            Err(ArchiveError::TooLarge(limits.max_total_bytes))
+        } else if total_bytes > RATIO_FLOOR_BYTES
+            && total_bytes > self.compressed_bytes.saturating_mul(limits.max_ratio)
+        {
+            Err(ArchiveError::CompressionRatio(limits.max_ratio))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The exceeded limit behind `error`, if that is what stopped a read.
+    fn explain(&self, error: ArchiveError) -> ArchiveError {
+        self.check().err().unwrap_or(error)
+    }
+}
+
+/// Counts what passes through `reader`, failing once a limit is exceeded.
+struct Metered<R> {
+    budget: Rc<Budget>,
+    reader: R,
+}
+
+impl<R: Read> Read for Metered<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+
+        self.budget
+            .total_bytes
+            .set(self.budget.total_bytes.get() + read as u64);
+        self.budget.check().map_err(io::Error::other)?;
+
+        Ok(read)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ArchiveFormat {
+    TarGz,
+    TarXz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar.xz") || name.ends_with(".txz") {
+            Some(Self::TarXz)
+        } else {
+            None
+        }
+    }
+
+    /// Recognizes an archive by its leading magic bytes.
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(Self::Zip)
+        } else if header.starts_with(&[0x1F, 0x8B]) {
+            Some(Self::TarGz)
+        } else if header.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::TarXz)
+        } else {
+            None
+        }
+    }
+
+    /// Sniffs the format of `reader`, leaving it rewound to the start.
+    pub fn detect<R: Read + Seek>(reader: &mut R) -> Result<Self, ArchiveError> {
+        let mut header = Vec::with_capacity(6);
+
+        reader.by_ref().take(6).read_to_end(&mut header)?;
+        reader.rewind()?;
+
+        Self::sniff(&header).ok_or(ArchiveError::UnknownFormat)
+    }
+}
+
+/// A file in an archive, positioned to be read.
+pub struct ArchiveEntry<'a> {
+    // Relative to the archive root; entries of nested archives sit below the nested
+    // archive's own path
+    pub path: PathBuf,
+    pub reader: &'a mut dyn Read,
+    // Uncompressed size, as recorded by the archive
+    pub size: u64,
+}
+
+pub type Visitor<'v> = dyn FnMut(ArchiveEntry<'_>) -> Result<(), ArchiveError> + 'v;
+
+/// An archive format that can list its files.
+pub trait ArchiveReader {
+    /// Calls `visit` with every regular file, in archive order.
+    fn visit(&mut self, visit: &mut Visitor<'_>) -> Result<(), ArchiveError>;
+}
+
+pub struct ZipReader<R>(ZipArchive<R>);
+
+impl<R: Read + Seek> ArchiveReader for ZipReader<R> {
+    fn visit(&mut self, visit: &mut Visitor<'_>) -> Result<(), ArchiveError> {
+        for i in 0..self.0.len() {
+            let mut zip_file = self.0.by_index(i)?;
+
+            if zip_file.is_dir() {
+                continue;
+            }
+
+            let path = zip_file
+                .enclosed_name()
+                .ok_or_else(|| ArchiveError::UnsafePath(zip_file.name().to_owned()))?;
+
+            let size = zip_file.size();
+
+            visit(ArchiveEntry {
+                path,
+                reader: &mut zip_file,
+                size,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct TarReader<R: Read>(tar::Archive<R>);
+
+impl<R: Read> ArchiveReader for TarReader<R> {
+    fn visit(&mut self, visit: &mut Visitor<'_>) -> Result<(), ArchiveError> {
+        for entry in self.0.entries()? {
+            let mut entry = entry?;
+
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let raw_path = entry.path()?.into_owned();
+
+            let path = enclosed(&raw_path)
+                .ok_or_else(|| ArchiveError::UnsafePath(raw_path.display().to_string()))?;
+
+            let size = entry.size();
+
+            visit(ArchiveEntry {
+                path,
+                reader: &mut entry,
+                size,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `path` if it stays inside the directory it is extracted to.
+fn enclosed(path: &Path) -> Option<PathBuf> {
+    let mut enclosed = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => enclosed.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    (!enclosed.as_os_str().is_empty()).then_some(enclosed)
+}
+
+/// Opens `reader` as a `format` archive. A tarball's whole decompressed stream is
+/// metered, so entries that are skipped still count against `budget`.
+fn open<'a, R: Read + Seek + 'a>(
+    format: ArchiveFormat,
+    reader: R,
+    budget: &Rc<Budget>,
+) -> Result<Box<dyn ArchiveReader + 'a>, ArchiveError> {
+    Ok(match format {
+        ArchiveFormat::Zip => Box::new(ZipReader(ZipArchive::new(reader)?)),
+        ArchiveFormat::TarGz => Box::new(TarReader(tar::Archive::new(Metered {
+            budget: budget.clone(),
+            reader: GzDecoder::new(reader),
+        }))),
+        ArchiveFormat::TarXz => Box::new(TarReader(tar::Archive::new(Metered {
+            budget: budget.clone(),
+            reader: XzDecoder::new(reader),
+        }))),
+    })
+}
+
+fn visit_nested(
+    format: ArchiveFormat,
+    reader: Box<dyn ArchiveReader + '_>,
+    prefix: &Path,
+    depth: usize,
+    budget: &Rc<Budget>,
+    visit: &mut Visitor<'_>,
+) -> Result<(), ArchiveError> {
+    let mut reader = reader;
+
+    // Tar entries are counted as the decompressed stream is read; zip entries are
+    // decompressed separately, so only the ones read count
+    let meter_entries = format == ArchiveFormat::Zip;
+
+    let visited = reader.visit(&mut |entry: ArchiveEntry<'_>| {
+        budget.entries.set(budget.entries.get() + 1);
+        budget.check()?;
+
+        let path = prefix.join(&entry.path);
+
+        match ArchiveFormat::from_path(&entry.path) {
+            Some(format) if depth < MAX_NESTING => {
+                // Nested archives are small enough to open from memory
+                let mut bytes = Vec::new();
+
+                let read = if meter_entries {
+                    Metered {
+                        budget: budget.clone(),
+                        reader: entry.reader,
+                    }
+                    .read_to_end(&mut bytes)
+                } else {
+                    entry.reader.read_to_end(&mut bytes)
+                };
+
+                read.map_err(|error| budget.explain(error.into()))?;
+
+                visit_nested(
+                    format,
+                    open(format, Cursor::new(bytes), budget)?,
+                    &path,
+                    depth + 1,
+                    budget,
+                    visit,
+                )
+            }
+            Some(_) => {
+                log::warn!("Skipping {:?}, which is nested too deeply", path);
+
+                Ok(())
+            }
+            None if meter_entries => visit(ArchiveEntry {
+                path,
+                reader: &mut Metered {
+                    budget: budget.clone(),
+                    reader: entry.reader,
+                },
+                size: entry.size,
+            }),
+            None => visit(ArchiveEntry { path, ..entry }),
+        }
+    });
+
+    visited.map_err(|error| budget.explain(error))
+}
+
+/// Visits every file of the archive in `reader`, whatever its format, descending into
+/// archives it contains. Reading stops with an error once `limits` are exceeded.
+pub fn visit_files_with<R: Read + Seek>(
+    mut reader: R,
+    limits: ExtractionLimits,
+    visit: &mut Visitor<'_>,
+) -> Result<(), ArchiveError> {
+    let compressed_bytes = reader.seek(SeekFrom::End(0))?;
+    reader.rewind()?;
+
+    let format = ArchiveFormat::detect(&mut reader)?;
+
+    let budget = Rc::new(Budget {
+        compressed_bytes,
+        entries: Cell::new(0),
+        limits,
+        total_bytes: Cell::new(0),
+    });
+
+    visit_nested(
+        format,
+        open(format, reader, &budget)?,
+        Path::new(""),
+        0,
+        &budget,
+        visit,
+    )
+}
+
+/// [`visit_files_with`] under the default limits.
+pub fn visit_files<R: Read + Seek>(reader: R, visit: &mut Visitor<'_>) -> Result<(), ArchiveError> {
+    visit_files_with(reader, ExtractionLimits::default(), visit)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::firmware::cache::tests::zip_bytes;
+
+    pub(crate) fn tar_gz_bytes(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+
+        for (name, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+
+            builder.append_data(&mut header, name, *contents).unwrap();
+        }
+
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn list(bytes: Vec<u8>) -> Vec<(PathBuf, String)> {
+        let mut files = Vec::new();
+
+        visit_files(Cursor::new(bytes), &mut |entry| {
+            let mut contents = String::new();
+            entry.reader.read_to_string(&mut contents)?;
+
+            files.push((entry.path, contents));
+
+            Ok(())
+        })
+        .unwrap();
+
+        files
+    }
+
+    #[test]
+    fn reads_tarballs_and_nested_zips() {
+        let inner = zip_bytes(&[("M8_V4_0_1_MODEL02.hex", b"inner")]);
+
+        let outer = tar_gz_bytes(&[
+            ("beta/M8_V4_0_1_MODEL01.hex", b"outer"),
+            ("beta/bundle.zip", &inner),
+        ]);
+
+        assert_eq!(
+            list(outer),
+            vec![
+                (
+                    PathBuf::from("beta/M8_V4_0_1_MODEL01.hex"),
+                    "outer".to_owned()
+                ),
+                (
+                    PathBuf::from("beta/bundle.zip/M8_V4_0_1_MODEL02.hex"),
+                    "inner".to_owned()
+                ),
+            ]
+        );
+
+        // Only one level of nesting is opened
+        let twice = zip_bytes(&[("outer.zip", &zip_bytes(&[("inner.zip", &inner)]))]);
+        assert!(list(twice).is_empty());
+    }
+
+    #[test]
+    fn stops_at_limits() {
+        let bomb = zip_bytes(&[("M8_V4_0_1_MODEL01.hex", &vec![b'0'; 8 * 1024 * 1024])]);
+
+        let result = visit_files(Cursor::new(bomb), &mut |entry| {
+            io::copy(entry.reader, &mut io::sink())?;
+
+            Ok(())
+        });
+        assert!(matches!(result, Err(ArchiveError::CompressionRatio(100))));
+
+        let many = zip_bytes(&[("a.hex", b"a"), ("b.hex", b"b"), ("c.hex", b"c")]);
+
+        let limits = ExtractionLimits {
+            max_entries: 2,
+            ..Default::default()
+        };
+
+        let result = visit_files_with(Cursor::new(many), limits, &mut |_| Ok(()));
+        assert!(matches!(result, Err(ArchiveError::TooManyEntries(2))));
+
+        // A tarball's entries count even when nothing reads them
+        let tar_bomb = tar_gz_bytes(&[("README.txt", &vec![b'0'; 8 * 1024 * 1024])]);
+
+        let result = visit_files(Cursor::new(tar_bomb), &mut |_| Ok(()));
+        assert!(matches!(result, Err(ArchiveError::CompressionRatio(100))));
+    }
+
+    #[test]
+    fn recognizes_formats() {
+        assert_eq!(
+            ArchiveFormat::sniff(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]),
+            Some(ArchiveFormat::TarXz)
+        );
+        assert_eq!(
+            ArchiveFormat::from_path(Path::new("M8_BETA.TGZ")),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(ArchiveFormat::sniff(b":1000"), None);
+        assert_eq!(enclosed(Path::new("../escape.hex")), None);
+    }
+}
@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+
+use futures_util::future::join_all;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::mpsc;
+
+use crate::{
+    events::frontend_events::{UploadState, UploadStatus},
+    firmware::{
+        download::DownloadError,
+        download_firmware, intel_hex,
+        recovery::{self, FlashJob, FlashStage},
+        restore,
+        variant::{self, FirmwareImage},
+        version::M8Version,
+        ArchiveSource, ConnectedDevice, DeviceType,
+    },
+    serial::{provider::FirmwareUploader, tycmd::TyCmdUploader},
+    state::{AppState, AppStateData},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum BatchError {
+    #[error("A flash is already in progress")]
+    Busy,
+    #[error("No connected devices to flash")]
+    NoDevices,
+    #[error("No connected device has the tag {0}")]
+    UnknownDevice(String),
+}
+
+impl Serialize for BatchError {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum BatchStage {
+    Downloading,
+    Uploading,
+    Finished,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum DeviceOutcome {
+    Pending,
+    Flashing,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct BatchDevice {
+    pub device_type: DeviceType,
+    // Why the board wasn't flashed
+    pub error: Option<String>,
+    // Latest tycmd output for the board
+    pub log: Option<String>,
+    pub outcome: DeviceOutcome,
+    pub serial: String,
+    pub tag: String,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct BatchSummary {
+    pub failed: Vec<String>,
+    pub succeeded: Vec<String>,
+}
+
+/// One archive flashed to several boards, each reported on its own.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct BatchStatus {
+    pub devices: Vec<BatchDevice>,
+    // Failures that stopped every board, such as the download
+    pub error: Option<String>,
+    pub stage: BatchStage,
+    // Serials by result, once finished
+    pub summary: Option<BatchSummary>,
+}
+
+impl BatchStatus {
+    pub fn new(devices: &[ConnectedDevice]) -> Self {
+        Self {
+            devices: devices
+                .iter()
+                .map(|device| BatchDevice {
+                    device_type: device.device_type.clone(),
+                    error: None,
+                    log: None,
+                    outcome: DeviceOutcome::Pending,
+                    serial: device.ty_cmd_info.serial.clone(),
+                    tag: device.ty_cmd_info.tag.clone(),
+                })
+                .collect(),
+            error: None,
+            stage: BatchStage::Downloading,
+            summary: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.stage != BatchStage::Finished
+    }
+
+    fn device_mut(&mut self, tag: &str) -> Option<&mut BatchDevice> {
+        self.devices.iter_mut().find(|device| device.tag == tag)
+    }
+
+    fn progress(&mut self, tag: &str, status: UploadStatus) {
+        if let Some(device) = self.device_mut(tag) {
+            device.outcome = DeviceOutcome::Flashing;
+
+            if status.log.is_some() {
+                device.log = status.log;
+            }
+        }
+    }
+
+    fn complete(&mut self, tag: &str, result: Result<(), String>) {
+        if let Some(device) = self.device_mut(tag) {
+            match result {
+                Ok(()) => device.outcome = DeviceOutcome::Succeeded,
+                Err(error) => {
+                    device.error = Some(error);
+                    device.outcome = DeviceOutcome::Failed;
+                }
+            }
+        }
+    }
+
+    /// Ends the batch, failing every board that never got a result.
+    fn finish(&mut self, error: Option<String>) {
+        let mut summary = BatchSummary::default();
+
+        for device in &mut self.devices {
+            if device.outcome != DeviceOutcome::Succeeded {
+                device.outcome = DeviceOutcome::Failed;
+
+                if device.error.is_none() {
+                    device.error = error.clone();
+                }
+
+                summary.failed.push(device.serial.clone());
+            } else {
+                summary.succeeded.push(device.serial.clone());
+            }
+        }
+
+        self.error = error;
+        self.stage = BatchStage::Finished;
+        self.summary = Some(summary);
+    }
+}
+
+async fn update(app_handle: &AppHandle, change: impl FnOnce(&mut BatchStatus)) {
+    let state = app_handle.state::<AppState>();
+
+    let mut state_guard = state.lock().await;
+
+    if let Some(batch) = state_guard.batch.as_mut() {
+        change(batch);
+
+        let _ = app_handle.emit_to("main", "batch-flash-update", batch.clone());
+    }
+}
+
+/// The boards to flash: those named by `tags`, or every connected one.
+fn batch_devices(
+    state: &AppStateData,
+    tags: Option<Vec<String>>,
+) -> Result<Vec<ConnectedDevice>, BatchError> {
+    let mut devices = match tags {
+        Some(tags) => tags
+            .iter()
+            .map(|tag| {
+                state
+                    .devices
+                    .get(tag)
+                    .cloned()
+                    .ok_or_else(|| BatchError::UnknownDevice(tag.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        None => state.devices.values().cloned().collect(),
+    };
+
+    if devices.is_empty() {
+        return Err(BatchError::NoDevices);
+    }
+
+    devices.sort_by(|a, b| a.ty_cmd_info.tag.cmp(&b.ty_cmd_info.tag));
+
+    Ok(devices)
+}
+
+async fn flash_device(
+    app_handle: &AppHandle,
+    images: Vec<FirmwareImage>,
+    device: &ConnectedDevice,
+    version: Option<&M8Version>,
+    progress: mpsc::UnboundedSender<(String, UploadStatus)>,
+) -> Result<(), String> {
+    if device.needs_recovery() {
+        return Err(restore::unconfirmed_message(
+            &device.ty_cmd_info.tag,
+            &device.device_type,
+        ));
+    }
+
+    let target =
+        variant::select_for_device(images, device, version).map_err(|error| error.to_string())?;
+
+    let (_, warnings) = intel_hex::validate_image(
+        target.path(),
+        &device.device_type,
+        target.image().variant.version.as_ref(),
+    )
+    .map_err(|error| format!("Firmware image rejected: {}", error))?;
+
+    let tag = device.ty_cmd_info.tag.clone();
+
+    for warning in warnings {
+        let _ = progress.send((
+            tag.clone(),
+            UploadStatus {
+                log: Some(format!("Warning: {}", warning)),
+                state: UploadState::Starting,
+            },
+        ));
+    }
+
+    let uploader = TyCmdUploader {
+        app_handle: app_handle.clone(),
+    };
+
+    uploader
+        .upload_firmware(
+            target.path().to_str().unwrap_or_default(),
+            target.board_tag(),
+            Box::new(move |status| {
+                let _ = progress.send((tag.clone(), status));
+            }),
+        )
+        .await
+        .map_err(|error| error.to_string())
+}
+
+async fn run_batch(app_handle: &AppHandle, devices: Vec<ConnectedDevice>) {
+    let state = app_handle.state::<AppState>();
+    let state_guard = state.lock().await;
+
+    let version = state_guard.version.clone();
+
+    let local_path = match &state_guard.archive_source {
+        ArchiveSource::LocalPath(path) => Some(path.display().to_string()),
+        _ => None,
+    };
+
+    drop(state_guard);
+
+    recovery::begin(
+        app_handle,
+        FlashJob {
+            device_serial: None,
+            path: local_path,
+            stage: FlashStage::Downloading,
+            started_at: chrono::Utc::now().timestamp_millis(),
+            version: version.clone(),
+        },
+    )
+    .await;
+
+    let images = download_firmware(app_handle).await;
+
+    {
+        // The download reports through the single-device status, which is done with
+        let mut state_guard = state.lock().await;
+
+        state_guard.flashing = None;
+
+        let _ = state_guard.emit_device_state_update(app_handle);
+    }
+
+    let images = match images {
+        Ok(images) => images,
+        Err(error) => {
+            let message = if matches!(error.downcast_ref(), Some(DownloadError::Cancelled)) {
+                "Download cancelled".to_string()
+            } else {
+                format!("Failed to download firmware: {}", error)
+            };
+
+            log::warn!("Batch flash stopped: {}", message);
+
+            update(app_handle, |batch| batch.finish(Some(message))).await;
+
+            recovery::finish(app_handle);
+
+            return;
+        }
+    };
+
+    recovery::advance(app_handle, FlashStage::Uploading);
+
+    update(app_handle, |batch| batch.stage = BatchStage::Uploading).await;
+
+    let (progress, mut updates) = mpsc::unbounded_channel();
+
+    let uploads = join_all(devices.iter().map(|device| {
+        let progress = progress.clone();
+        let images = images.clone();
+        let version = version.as_ref();
+
+        async move {
+            let result = flash_device(app_handle, images, device, version, progress).await;
+
+            (device.ty_cmd_info.tag.clone(), result)
+        }
+    }));
+
+    // Only the uploads hold senders now, so updates end when they do
+    drop(progress);
+
+    let collect = async {
+        while let Some((tag, status)) = updates.recv().await {
+            update(app_handle, |batch| batch.progress(&tag, status)).await;
+        }
+    };
+
+    let (results, ()) = tokio::join!(uploads, collect);
+
+    let results: HashMap<String, Result<(), String>> = results.into_iter().collect();
+
+    update(app_handle, |batch| {
+        for (tag, result) in results {
+            batch.complete(&tag, result);
+        }
+
+        batch.finish(None);
+
+        log::info!("Batch flash finished: {:?}", batch.summary);
+    })
+    .await;
+
+    recovery::finish(app_handle);
+}
+
+/// Downloads the selected firmware once and flashes it to every board in `tags`, or to
+/// every connected board, all at the same time.
+#[tauri::command]
+pub async fn start_batch_flash(
+    app_handle: AppHandle,
+    tags: Option<Vec<String>>,
+) -> Result<BatchStatus, BatchError> {
+    let state = app_handle.state::<AppState>();
+
+    let mut state_guard = state.lock().await;
+
+    if state_guard.is_flashing() {
+        return Err(BatchError::Busy);
+    }
+
+    let devices = batch_devices(&state_guard, tags)?;
+
+    let batch = BatchStatus::new(&devices);
+
+    state_guard.batch = Some(batch.clone());
+
+    drop(state_guard);
+
+    tauri::async_runtime::spawn(async move {
+        let run_app_handle = app_handle.clone();
+
+        let run = tauri::async_runtime::spawn(async move {
+            run_batch(&run_app_handle, devices).await;
+        });
+
+        // A panic mustn't leave the batch running and every other flash refused
+        if let Err(error) = run.await {
+            log::error!("Batch flash failed: {}", error);
+
+            update(&app_handle, |batch| {
+                batch.finish(Some("Batch flash stopped unexpectedly".to_string()))
+            })
+            .await;
+
+            recovery::finish(&app_handle);
+        }
+    });
+
+    Ok(batch)
+}
+
+#[tauri::command]
+pub async fn get_batch_status(app_handle: AppHandle) -> Option<BatchStatus> {
+    let state = app_handle.state::<AppState>();
+
+    let batch = state.lock().await.batch.clone();
+
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::tests::sample_device;
+
+    fn device(serial: &str) -> ConnectedDevice {
+        ConnectedDevice {
+            device_type: DeviceType::MODEL02,
+            ..sample_device(serial, &format!("{}-Teensy", serial))
+        }
+    }
+
+    #[test]
+    fn reports_each_device_on_its_own() {
+        let mut batch = BatchStatus::new(&[device("1"), device("2"), device("3")]);
+        assert!(batch.is_running());
+
+        batch.progress(
+            "1-Teensy",
+            UploadStatus {
+                log: Some("upload@1-Teensy Uploading... 50%".into()),
+                state: UploadState::Uploading,
+            },
+        );
+        assert_eq!(batch.devices[0].outcome, DeviceOutcome::Flashing);
+        assert_eq!(batch.devices[1].outcome, DeviceOutcome::Pending);
+
+        batch.complete("1-Teensy", Ok(()));
+        batch.complete("2-Teensy", Err("Device busy".into()));
+        batch.finish(None);
+
+        assert!(!batch.is_running());
+        assert_eq!(
+            batch.summary,
+            Some(BatchSummary {
+                failed: vec!["2".into(), "3".into()],
+                succeeded: vec!["1".into()],
+            })
+        );
+        assert_eq!(batch.devices[1].error.as_deref(), Some("Device busy"));
+    }
+
+    #[test]
+    fn batches_need_known_devices() {
+        let mut state = AppStateData::default();
+        assert!(matches!(
+            batch_devices(&state, None),
+            Err(BatchError::NoDevices)
+        ));
+
+        for serial in ["2", "1"] {
+            state.track_device(device(serial));
+        }
+
+        let devices = batch_devices(&state, None).unwrap();
+        assert_eq!(devices[0].ty_cmd_info.serial, "1");
+
+        assert!(matches!(
+            batch_devices(&state, Some(vec!["9-Teensy".into()])),
+            Err(BatchError::UnknownDevice(_))
+        ));
+    }
+}
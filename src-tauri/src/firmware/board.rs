@@ -0,0 +1,170 @@
+use serde::Serialize;
+
+use crate::firmware::intel_hex::{HexImage, TeensyBoard};
+
+const BOARDS: [TeensyBoard; 3] = [
+    TeensyBoard::Teensy40,
+    TeensyBoard::Teensy41,
+    TeensyBoard::MicroMod,
+];
+
+// The boot ROM reads a FlexSPI NOR configuration block from the very start of flash
+const FLASH_CONFIG_ADDRESS: u32 = 0x6000_0000;
+
+const FLASH_CONFIG_TAG: &[u8] = b"FCFB";
+
+// Teensyduino's bootdata.c sets `sflashA1Size` to the size of the board's flash chip
+const FLASH_SIZE_OFFSET: u32 = 0x50;
+
+// Image vector table, which follows the configuration block on every Teensy 4.x
+const IVT_ADDRESS: u32 = 0x6000_1000;
+
+const IVT_TAG: u8 = 0xD1;
+
+// Board names some builds carry in their USB descriptors or version banners. These are
+// only consulted when the configuration block doesn't settle the question.
+const BOARD_STRINGS: [(&str, TeensyBoard); 3] = [
+    ("Teensy MicroMod", TeensyBoard::MicroMod),
+    ("Teensy 4.1", TeensyBoard::Teensy41),
+    ("Teensy 4.0", TeensyBoard::Teensy40),
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum BoardEvidence {
+    EmbeddedString,
+    FlashConfig,
+    ImageSize,
+}
+
+/// What the contents of a hex image say about the board it was built for.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct BoardDetection {
+    pub board: Option<TeensyBoard>,
+    // Boards whose program flash can hold the image
+    pub candidates: Vec<TeensyBoard>,
+    pub evidence: Option<BoardEvidence>,
+}
+
+fn flash_config_board(image: &HexImage) -> Option<TeensyBoard> {
+    if image.bytes_at(FLASH_CONFIG_ADDRESS, FLASH_CONFIG_TAG.len())? != FLASH_CONFIG_TAG {
+        return None;
+    }
+
+    if image.bytes_at(IVT_ADDRESS, 1)? != [IVT_TAG] {
+        return None;
+    }
+
+    let size = image.bytes_at(FLASH_CONFIG_ADDRESS + FLASH_SIZE_OFFSET, 4)?;
+    let size = u32::from_le_bytes(size.try_into().ok()?);
+
+    BOARDS
+        .into_iter()
+        .find(|board| board.flash_chip_size() == size)
+}
+
+fn embedded_string_board(image: &HexImage) -> Option<TeensyBoard> {
+    let mut found = BOARD_STRINGS
+        .iter()
+        .filter(|(needle, _)| image.contains(needle.as_bytes()))
+        .map(|(_, board)| *board);
+
+    match (found.next(), found.next()) {
+        (Some(board), None) => Some(board),
+        _ => None,
+    }
+}
+
+/// Infers the target board from the flash configuration block, then embedded board
+/// names, and finally from which boards are large enough to hold the image.
+pub fn detect(image: &HexImage) -> BoardDetection {
+    let candidates: Vec<TeensyBoard> = BOARDS
+        .into_iter()
+        .filter(|board| image.validate_for(*board).is_ok())
+        .collect();
+
+    let fits = |board: &TeensyBoard| candidates.contains(board);
+
+    let (board, evidence) = if let Some(board) = flash_config_board(image).filter(fits) {
+        (Some(board), Some(BoardEvidence::FlashConfig))
+    } else if let Some(board) = embedded_string_board(image).filter(fits) {
+        (Some(board), Some(BoardEvidence::EmbeddedString))
+    } else if let [board] = candidates.as_slice() {
+        (Some(*board), Some(BoardEvidence::ImageSize))
+    } else {
+        (None, None)
+    };
+
+    BoardDetection {
+        board,
+        candidates,
+        evidence,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firmware::{
+        intel_hex::{parse, tests::hex_file, HexError, HexWarning},
+        DeviceType,
+    };
+
+    fn boot_image(flash_size: u32, extra: &[u8]) -> HexImage {
+        let mut data = vec![0u8; 0x1000 + 0x40];
+
+        data[..4].copy_from_slice(FLASH_CONFIG_TAG);
+        data[0x50..0x54].copy_from_slice(&flash_size.to_le_bytes());
+        data[0x1000] = IVT_TAG;
+        data.extend_from_slice(extra);
+
+        parse(&hex_file(FLASH_CONFIG_ADDRESS, &data)).unwrap()
+    }
+
+    #[test]
+    fn detects_board_from_flash_config() {
+        for board in BOARDS {
+            let detection = detect(&boot_image(board.flash_chip_size(), &[]));
+
+            assert_eq!(detection.board, Some(board));
+            assert_eq!(detection.evidence, Some(BoardEvidence::FlashConfig));
+        }
+    }
+
+    #[test]
+    fn falls_back_to_strings_and_size() {
+        let named = detect(&boot_image(0, b"M8 on Teensy 4.1"));
+        assert_eq!(named.board, Some(TeensyBoard::Teensy41));
+        assert_eq!(named.evidence, Some(BoardEvidence::EmbeddedString));
+
+        // Only a MicroMod has room for an image reaching past 8 MiB
+        let large = parse(&hex_file(0x6090_0000, &[0; 16])).unwrap();
+        let detection = detect(&large);
+        assert_eq!(detection.board, Some(TeensyBoard::MicroMod));
+        assert_eq!(detection.evidence, Some(BoardEvidence::ImageSize));
+
+        let unknown = detect(&boot_image(0, &[]));
+        assert_eq!(unknown.board, None);
+        assert_eq!(unknown.candidates.len(), 3);
+    }
+
+    #[test]
+    fn only_the_flash_config_refuses_a_board_mismatch() {
+        let named = boot_image(0, b"M8 on Teensy 4.1");
+        assert_eq!(
+            named.validate_for_device(&DeviceType::MODEL01, None),
+            Ok(vec![HexWarning::BoardMismatch {
+                board: TeensyBoard::Teensy40,
+                detected: TeensyBoard::Teensy41,
+                evidence: BoardEvidence::EmbeddedString,
+            }])
+        );
+
+        let configured = boot_image(TeensyBoard::Teensy41.flash_chip_size(), &[]);
+        assert!(matches!(
+            configured.validate_for_device(&DeviceType::MODEL01, None),
+            Err(HexError::BoardMismatch { .. })
+        ));
+
+        assert!(!named.contains(&[]));
+    }
+}
@@ -0,0 +1,621 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::firmware::{
+    archive::{self, ArchiveError},
+    variant::{self, FirmwareImage, FirmwareVariant, VariantError},
+    version::M8Version,
+};
+
+// Lives under the app cache directory, with one subdirectory per archive hash
+pub const CACHE_DIR_NAME: &str = "firmware";
+
+const INDEX_FILE_NAME: &str = "index.json";
+
+const STAGING_PREFIX: &str = ".staging-";
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    #[error("Firmware cache I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid firmware archive: {0}")]
+    Archive(#[from] ArchiveError),
+    #[error("Failed to write firmware cache index: {0}")]
+    Index(#[from] serde_json::Error),
+    #[error(transparent)]
+    Variant(#[from] VariantError),
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CachedImage {
+    pub file_name: String,
+    pub sha256: String,
+    pub size: u64,
+    pub variant: FirmwareVariant,
+}
+
+/// One extracted archive, stored in the directory named after its SHA-256.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CacheEntry {
+    pub archive_sha256: String,
+    pub cached_at: i64,
+    pub images: Vec<CachedImage>,
+    #[serde(default)]
+    pub last_used_at: i64,
+    // Pinned entries are never evicted
+    #[serde(default)]
+    pub pinned: bool,
+    pub source_url: Option<String>,
+    pub version: Option<M8Version>,
+}
+
+impl CacheEntry {
+    pub fn size(&self) -> u64 {
+        self.images.iter().map(|image| image.size).sum()
+    }
+
+    fn last_used(&self) -> i64 {
+        self.last_used_at.max(self.cached_at)
+    }
+}
+
+/// Limits the cache is trimmed back to, least recently used entries first.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct EvictionPolicy {
+    pub max_age_days: Option<u32>,
+    pub max_bytes: Option<u64>,
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> Self {
+        Self {
+            max_age_days: Some(180),
+            max_bytes: Some(512 * 1024 * 1024),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CacheIndex {
+    pub entries: Vec<CacheEntry>,
+}
+
+pub struct FirmwareCache {
+    index: CacheIndex,
+    root: PathBuf,
+}
+
+pub fn sha256_hex(reader: &mut impl Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+
+    io::copy(reader, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Writes `contents` next to `path` and renames it into place, so readers never see a
+/// partially written file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(".tmp");
+
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    fs::rename(&temp_path, path)
+}
+
+impl FirmwareCache {
+    /// Opens the cache under `root`, creating it if needed. An unreadable index is
+    /// treated as empty; the directories it described get replaced as they're re-cached.
+    pub fn open(root: PathBuf) -> Result<Self, CacheError> {
+        fs::create_dir_all(&root)?;
+
+        let index = match fs::read_to_string(root.join(INDEX_FILE_NAME)) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|error| {
+                log::warn!("Discarding unreadable firmware cache index: {}", error);
+
+                CacheIndex::default()
+            }),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => CacheIndex::default(),
+            Err(error) => return Err(error.into()),
+        };
+
+        Ok(Self { index, root })
+    }
+
+    pub fn entries(&self) -> &[CacheEntry] {
+        &self.index.entries
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn entry_dir(&self, archive_sha256: &str) -> PathBuf {
+        self.root.join(archive_sha256)
+    }
+
+    fn save(&self) -> Result<(), CacheError> {
+        write_atomic(
+            &self.root.join(INDEX_FILE_NAME),
+            serde_json::to_string_pretty(&self.index)?.as_bytes(),
+        )?;
+
+        Ok(())
+    }
+
+    /// The images of `entry`, if every one of them is still on disk and unmodified.
+    fn verified_images(&self, entry: &CacheEntry) -> Option<Vec<FirmwareImage>> {
+        let dir = self.entry_dir(&entry.archive_sha256);
+
+        entry
+            .images
+            .iter()
+            .map(|image| {
+                let path = dir.join(&image.file_name);
+
+                let sha256 = File::open(&path)
+                    .and_then(|mut file| sha256_hex(&mut file))
+                    .ok()?;
+
+                if sha256 != image.sha256 {
+                    log::warn!("Cached image {:?} no longer matches its hash", path);
+
+                    return None;
+                }
+
+                Some(FirmwareImage {
+                    path,
+                    variant: image.variant.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Records that an entry was just used, which keeps it from being evicted. Only the
+    /// copy in memory changes; it's written out with the next change to the index.
+    fn touch(&mut self, archive_sha256: &str) {
+        let now = chrono::Utc::now().timestamp_millis();
+
+        for entry in &mut self.index.entries {
+            if entry.archive_sha256 == archive_sha256 {
+                entry.last_used_at = now;
+            }
+        }
+    }
+
+    /// Looks up a previously downloaded archive so a re-flash can skip the network.
+    pub fn find_source(
+        &mut self,
+        source_url: &str,
+        version: Option<&M8Version>,
+    ) -> Option<Vec<FirmwareImage>> {
+        let (archive_sha256, images) = self
+            .index
+            .entries
+            .iter()
+            .filter(|entry| {
+                entry.source_url.as_deref() == Some(source_url) && entry.version.as_ref() == version
+            })
+            .find_map(|entry| Some((entry.archive_sha256.clone(), self.verified_images(entry)?)))?;
+
+        self.touch(&archive_sha256);
+
+        Some(images)
+    }
+
+    pub fn find_archive(&mut self, archive_sha256: &str) -> Option<Vec<FirmwareImage>> {
+        let images = self
+            .index
+            .entries
+            .iter()
+            .find(|entry| entry.archive_sha256 == archive_sha256)
+            .and_then(|entry| self.verified_images(entry))?;
+
+        self.touch(archive_sha256);
+
+        Some(images)
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.index.entries.iter().map(CacheEntry::size).sum()
+    }
+
+    /// Deletes the entries `remove` selects, returning them.
+    pub fn remove_where(
+        &mut self,
+        mut remove: impl FnMut(&CacheEntry) -> bool,
+    ) -> Result<Vec<CacheEntry>, CacheError> {
+        let (removed, kept) = std::mem::take(&mut self.index.entries)
+            .into_iter()
+            .partition::<Vec<_>, _>(|entry| remove(entry));
+
+        self.index.entries = kept;
+
+        self.save()?;
+
+        for entry in &removed {
+            log::info!(
+                "Removing cached firmware {:?} ({})",
+                entry.version,
+                entry.archive_sha256
+            );
+
+            match fs::remove_dir_all(self.entry_dir(&entry.archive_sha256)) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+                _ => {}
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Pins or unpins every entry of `version`, returning how many there were.
+    pub fn set_pinned(&mut self, version: &M8Version, pinned: bool) -> Result<usize, CacheError> {
+        let mut count = 0;
+
+        for entry in &mut self.index.entries {
+            if entry.version.as_ref() == Some(version) {
+                entry.pinned = pinned;
+                count += 1;
+            }
+        }
+
+        self.save()?;
+
+        Ok(count)
+    }
+
+    /// Removes unpinned entries that have gone unused for longer than the policy allows,
+    /// then the least recently used ones until the cache fits. The most recently used
+    /// entry is always kept, so the firmware about to be flashed stays on disk.
+    pub fn evict(
+        &mut self,
+        policy: &EvictionPolicy,
+        now: i64,
+    ) -> Result<Vec<CacheEntry>, CacheError> {
+        let mut by_use: Vec<&CacheEntry> = self.index.entries.iter().collect();
+        by_use.sort_by_key(|entry| std::cmp::Reverse(entry.last_used()));
+
+        let mut evicted: Vec<String> = Vec::new();
+
+        // Pinned entries take up room whether or not they were used recently
+        let mut retained_size: u64 = by_use
+            .iter()
+            .filter(|entry| entry.pinned)
+            .map(|entry| entry.size())
+            .sum();
+
+        for (position, entry) in by_use.into_iter().enumerate() {
+            if entry.pinned {
+                continue;
+            }
+
+            retained_size += entry.size();
+
+            if position == 0 {
+                continue;
+            }
+
+            let expired = policy
+                .max_age_days
+                .is_some_and(|days| now - entry.last_used() > i64::from(days) * MILLIS_PER_DAY);
+
+            let over_size = policy
+                .max_bytes
+                .is_some_and(|max_bytes| retained_size > max_bytes);
+
+            if expired || over_size {
+                retained_size -= entry.size();
+                evicted.push(entry.archive_sha256.clone());
+            }
+        }
+
+        if evicted.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.remove_where(|entry| evicted.contains(&entry.archive_sha256))
+    }
+
+    /// Extracts the entries of `archive` accepted by `filter`, including those of
+    /// archives nested inside it, into the directory for its hash and records them in
+    /// the index. Files are written to a staging directory that is renamed into place
+    /// once complete.
+    pub fn insert_archive<R: Read + Seek>(
+        &mut self,
+        mut archive: R,
+        filter: fn(&Path) -> bool,
+        source_url: Option<String>,
+        version: Option<M8Version>,
+    ) -> Result<Vec<FirmwareImage>, CacheError> {
+        let archive_sha256 = sha256_hex(&mut archive)?;
+
+        archive.rewind()?;
+
+        if let Some(images) = self.find_archive(&archive_sha256) {
+            log::info!("Archive {} is already cached", archive_sha256);
+
+            return Ok(images);
+        }
+
+        let staging = self
+            .root
+            .join(format!("{}{}", STAGING_PREFIX, archive_sha256));
+
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+
+        fs::create_dir_all(&staging)?;
+
+        let mut file_names: Vec<String> = Vec::new();
+
+        let extracted = archive::visit_files(archive, &mut |entry| {
+            if !filter(&entry.path) {
+                return Ok(());
+            }
+
+            let file_name = entry
+                .path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| ArchiveError::UnsafePath(entry.path.display().to_string()))?
+                .to_owned();
+
+            // Entries are flattened, so same-named files in different folders collide
+            if file_names.contains(&file_name) {
+                return Err(ArchiveError::DuplicateName(file_name));
+            }
+
+            let mut file = File::create(staging.join(&file_name))?;
+            io::copy(entry.reader, &mut file)?;
+            file.sync_all()?;
+
+            file_names.push(file_name);
+
+            Ok(())
+        });
+
+        if let Err(error) = extracted {
+            let _ = fs::remove_dir_all(&staging);
+
+            return Err(error.into());
+        }
+
+        let dir = self.entry_dir(&archive_sha256);
+
+        // Left behind by an index that was lost or rewritten
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+
+        fs::rename(&staging, &dir)?;
+
+        let images =
+            variant::classify_archive(file_names.iter().map(|name| dir.join(name)).collect())
+                .inspect_err(|_| {
+                    let _ = fs::remove_dir_all(&dir);
+                })?;
+
+        let cached_images = images
+            .iter()
+            .zip(&file_names)
+            .map(|(image, file_name)| {
+                let mut file = File::open(&image.path)?;
+
+                Ok(CachedImage {
+                    file_name: file_name.clone(),
+                    sha256: sha256_hex(&mut file)?,
+                    size: file.metadata()?.len(),
+                    variant: image.variant.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>, io::Error>>()?;
+
+        let version = version.or_else(|| {
+            images
+                .iter()
+                .find_map(|image| image.variant.version.clone())
+        });
+
+        let pinned = self
+            .index
+            .entries
+            .iter()
+            .any(|entry| entry.archive_sha256 == archive_sha256 && entry.pinned);
+
+        self.index
+            .entries
+            .retain(|entry| entry.archive_sha256 != archive_sha256);
+
+        let now = chrono::Utc::now().timestamp_millis();
+
+        self.index.entries.push(CacheEntry {
+            archive_sha256,
+            cached_at: now,
+            images: cached_images,
+            last_used_at: now,
+            pinned,
+            source_url,
+            version,
+        });
+
+        self.save()?;
+
+        Ok(images)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::firmware::{is_hex, DeviceType};
+
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    pub(crate) fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("m8-cache-{}-{}", name, std::process::id()));
+
+        let _ = fs::remove_dir_all(&root);
+
+        root
+    }
+
+    pub(crate) fn zip_bytes(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(io::Cursor::new(Vec::new()));
+
+        for (name, contents) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn caches_archives_by_hash() {
+        let root = temp_root("insert");
+        let url = "https://example.com/M8Firmware_V4_0_1.zip";
+        let version: M8Version = "4.0.1".parse().unwrap();
+
+        let archive = zip_bytes(&[
+            ("M8_V4_0_1_MODEL01.hex", b":00000001FF\n"),
+            ("M8_V4_0_1_MODEL02.hex", b":00000001FF\n"),
+            ("README.txt", b"skip me"),
+        ]);
+
+        let mut cache = FirmwareCache::open(root.clone()).unwrap();
+        let images = cache
+            .insert_archive(
+                io::Cursor::new(&archive),
+                is_hex,
+                Some(url.into()),
+                Some(version.clone()),
+            )
+            .unwrap();
+
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[1].variant.model, DeviceType::MODEL02);
+
+        let sha256 = sha256_hex(&mut io::Cursor::new(&archive)).unwrap();
+        assert!(images[0].path.starts_with(root.join(&sha256)));
+
+        // A fresh handle reads the index back from disk
+        let mut reopened = FirmwareCache::open(root.clone()).unwrap();
+        assert_eq!(reopened.entries()[0].source_url.as_deref(), Some(url));
+        assert_eq!(
+            reopened.find_source(url, Some(&version)),
+            Some(images.clone())
+        );
+        assert_eq!(reopened.find_source(url, None), None);
+
+        // Tampered files are not served
+        fs::write(&images[0].path, b"corrupt").unwrap();
+        assert_eq!(reopened.find_source(url, Some(&version)), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rejects_colliding_file_names() {
+        let root = temp_root("collide");
+        let mut cache = FirmwareCache::open(root.clone()).unwrap();
+
+        let archive = zip_bytes(&[
+            ("stable/M8_V4_0_1_MODEL01.hex", b":00000001FF\n"),
+            ("beta/M8_V4_0_1_MODEL01.hex", b":00000001FF\n"),
+        ]);
+
+        let result = cache.insert_archive(io::Cursor::new(archive), is_hex, None, None);
+        assert!(matches!(
+            result,
+            Err(CacheError::Archive(ArchiveError::DuplicateName(_)))
+        ));
+
+        // Nothing is left behind in the cache
+        assert!(cache.entries().is_empty());
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 0);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn evicts_least_recently_used_unpinned_entries() {
+        let root = temp_root("evict");
+        let mut cache = FirmwareCache::open(root.clone()).unwrap();
+
+        for version in ["4.0.0", "4.0.1", "4.1.0"] {
+            let name = format!("M8_V{}_MODEL01.hex", version.replace('.', "_"));
+
+            cache
+                .insert_archive(
+                    io::Cursor::new(zip_bytes(&[(name.as_str(), b":00000001FF\n")])),
+                    is_hex,
+                    None,
+                    Some(version.parse().unwrap()),
+                )
+                .unwrap();
+        }
+
+        let age = |cache: &mut FirmwareCache, index: usize, last_used_at: i64| {
+            cache.index.entries[index].last_used_at = last_used_at;
+            cache.index.entries[index].cached_at = last_used_at;
+        };
+        age(&mut cache, 0, 1_000);
+        age(&mut cache, 1, 2_000);
+        age(&mut cache, 2, 3_000);
+
+        cache.set_pinned(&"4.0.0".parse().unwrap(), true).unwrap();
+
+        // Room for two entries; the pinned oldest one stays, so 4.0.1 goes
+        let entry_size = cache.entries()[0].size();
+        let policy = EvictionPolicy {
+            max_age_days: None,
+            max_bytes: Some(entry_size * 2),
+        };
+        let evicted = cache.evict(&policy, 4_000).unwrap();
+
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].version, Some("4.0.1".parse().unwrap()));
+        assert!(!root.join(&evicted[0].archive_sha256).exists());
+        assert_eq!(cache.total_size(), entry_size * 2);
+
+        // Everything has expired, but the most recent entry is kept
+        let policy = EvictionPolicy {
+            max_age_days: Some(1),
+            max_bytes: None,
+        };
+        assert_eq!(cache.evict(&policy, 10 * MILLIS_PER_DAY).unwrap().len(), 0);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rewrites_shorter_files_completely() {
+        let root = temp_root("truncate");
+
+        let path = root.join("file.hex");
+        fs::create_dir_all(&root).unwrap();
+        write_atomic(&path, b"a much longer original file").unwrap();
+        write_atomic(&path, b"short").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"short");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
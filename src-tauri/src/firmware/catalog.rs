@@ -0,0 +1,588 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::{
+    firmware::{
+        archive::ArchiveFormat,
+        cache::write_atomic,
+        changelog::{parse_changelog, ChangelogSection, ChangelogVersion},
+        github::GitHubClient,
+        integrity::ExpectedArchive,
+        is_hex,
+        sources::{self, FirmwareSource},
+        version::M8Version,
+        ArchiveSource,
+    },
+    state::{AppState, AppStateData},
+};
+
+const CHANGELOG_PATH: &str = "changelog.txt";
+
+const RELEASES_PATH: &str = "Releases";
+
+// The latest firmware is not present in the Releases directory, and lives at the top-level
+const LATEST_ARCHIVE_PATH: &str = "M8Firmware.zip";
+
+// How long a fetched catalog is served from memory before GitHub is asked again
+const CATALOG_TTL_MILLIS: i64 = 10 * 60 * 1000;
+
+// While offline, GitHub is asked again this often rather than on every lookup
+const OFFLINE_RETRY_MILLIS: i64 = 60 * 1000;
+
+// The last catalog fetched successfully, kept in the app cache directory for offline use
+const CATALOG_FILE_NAME: &str = "catalog.json";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CatalogError {
+    #[error("Failed to reach the firmware server: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("Unexpected response ({status}): {body}")]
+    Status { body: String, status: u16 },
+    #[error("Failed to parse the firmware listing: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("Invalid request header: {0}")]
+    Header(#[from] reqwest::header::InvalidHeaderValue),
+    #[error("Version {0} is not in the firmware catalog")]
+    UnknownVersion(String),
+    #[error("Version {0} has no downloadable archive")]
+    NoArchive(String),
+    #[error("No firmware sources are configured")]
+    NoSources,
+    #[error("Invalid firmware source: {0}")]
+    InvalidSource(String),
+    #[error("{0} is not a firmware archive or .hex file")]
+    UnsupportedFile(String),
+    #[error("Unable to save settings: {0}")]
+    Store(String),
+    #[error("Rate limited by GitHub until {}", .until.format("%H:%M"))]
+    RateLimited {
+        until: chrono::DateTime<chrono::Local>,
+    },
+}
+
+impl CatalogError {
+    /// Whether the server couldn't be reached at all, as opposed to answering with an error.
+    fn is_offline(&self) -> bool {
+        match self {
+            CatalogError::Request(error) => error.is_connect() || error.is_timeout(),
+            _ => false,
+        }
+    }
+}
+
+impl Serialize for CatalogError {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_str())
+    }
+}
+
+/// An entry of the GitHub contents API listing, trimmed to the fields we use.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ContentsEntry {
+    pub name: String,
+    pub path: String,
+    pub sha: String,
+    pub size: u64,
+}
+
+/// A firmware version known to the catalog, with its archive if one is published.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FirmwareRelease {
+    pub changelog: Vec<ChangelogSection>,
+    pub date: Option<String>,
+    pub download_url: Option<String>,
+    pub path: String,
+    pub sha: Option<String>,
+    pub size: u64,
+    pub version: M8Version,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FirmwareCatalog {
+    // When GitHub was last asked, whether or not it answered
+    #[serde(default)]
+    pub checked_at: i64,
+    pub fetched_at: i64,
+    pub releases: Vec<FirmwareRelease>,
+}
+
+impl FirmwareCatalog {
+    pub fn is_fresh(&self, offline: bool) -> bool {
+        let ttl = if offline {
+            OFFLINE_RETRY_MILLIS
+        } else {
+            CATALOG_TTL_MILLIS
+        };
+
+        chrono::Utc::now().timestamp_millis() - self.checked_at < ttl
+    }
+
+    pub fn find(&self, version: &M8Version) -> Option<&FirmwareRelease> {
+        self.releases
+            .iter()
+            .find(|release| &release.version == version)
+    }
+
+    /// The newest release that has an archive to download.
+    pub fn latest(&self) -> Option<&FirmwareRelease> {
+        self.releases
+            .iter()
+            .filter(|release| release.download_url.is_some())
+            .max_by(|a, b| a.version.cmp(&b.version))
+    }
+}
+
+/// Merges the changelog versions with the published archives.
+///
+/// Letter-suffixed patches (`3.2.1A`) share the archive of their base version, which is
+/// assigned to the latest patch of the group. Archives the changelog doesn't mention are
+/// kept as releases without a date.
+pub fn merge_releases(
+    changelog: Vec<ChangelogVersion>,
+    archives: Vec<(M8Version, ContentsEntry)>,
+    source: &FirmwareSource,
+) -> Vec<FirmwareRelease> {
+    let mut releases: Vec<FirmwareRelease> = changelog
+        .into_iter()
+        .filter_map(|entry| match entry.version.parse::<M8Version>() {
+            Ok(version) => Some(FirmwareRelease {
+                changelog: entry.changelog,
+                date: Some(entry.date),
+                download_url: None,
+                path: String::new(),
+                sha: None,
+                size: 0,
+                version,
+            }),
+            Err(error) => {
+                log::warn!("Skipping changelog entry: {}", error);
+
+                None
+            }
+        })
+        .collect();
+
+    // Which changelog version should receive the archive for a base version
+    let mut archive_targets: HashMap<M8Version, M8Version> = HashMap::new();
+
+    for version in releases.iter().map(|release| &release.version) {
+        let target = archive_targets
+            .entry(version.base())
+            .or_insert_with(|| version.clone());
+
+        if version > target {
+            *target = version.clone();
+        }
+    }
+
+    for (version, entry) in archives {
+        let target = archive_targets
+            .get(&version.base())
+            .cloned()
+            .unwrap_or(version);
+
+        let index = match releases.iter().position(|r| r.version == target) {
+            Some(index) => index,
+            None => {
+                releases.push(FirmwareRelease {
+                    changelog: Vec::new(),
+                    date: None,
+                    download_url: None,
+                    path: String::new(),
+                    sha: None,
+                    size: 0,
+                    version: target,
+                });
+
+                releases.len() - 1
+            }
+        };
+
+        let release = &mut releases[index];
+
+        release.download_url = Some(source.url(&entry.path));
+        release.path = entry.path;
+        release.sha = Some(entry.sha);
+        release.size = entry.size;
+    }
+
+    releases.sort_by(|a, b| b.version.cmp(&a.version));
+
+    releases
+}
+
+async fn fetch_releases_from(
+    client: &GitHubClient,
+    source: &FirmwareSource,
+    token: Option<&str>,
+) -> Result<Vec<FirmwareRelease>, CatalogError> {
+    log::info!("Fetching firmware catalog from {}", source.name);
+
+    let changelog = client
+        .get_contents(source, token, CHANGELOG_PATH, "raw")
+        .await?;
+
+    let versions = parse_changelog(&changelog);
+
+    let mut entries: Vec<ContentsEntry> = serde_json::from_str(
+        &client
+            .get_contents(source, token, RELEASES_PATH, "raw")
+            .await?,
+    )?;
+
+    entries.push(serde_json::from_str(
+        &client
+            .get_contents(source, token, LATEST_ARCHIVE_PATH, "object")
+            .await?,
+    )?);
+
+    // The latest firmware doesn't have the version in its filename. We pair it up
+    // with the newest version in the changelog.
+    let latest = versions
+        .iter()
+        .filter_map(|entry| entry.version.parse::<M8Version>().ok())
+        .max();
+
+    let archives = entries
+        .into_iter()
+        .filter_map(|entry| {
+            M8Version::from_file_name(&entry.name)
+                .or_else(|| latest.clone())
+                .map(|version| (version, entry))
+        })
+        .collect();
+
+    Ok(merge_releases(versions, archives, source))
+}
+
+/// Fetches the whole catalog from the first source that answers, so that every
+/// download URL points at the same source.
+pub async fn fetch_releases(
+    client: &GitHubClient,
+    sources: &[FirmwareSource],
+    token: Option<&str>,
+) -> Result<Vec<FirmwareRelease>, CatalogError> {
+    let (releases, source) = sources::with_failover(sources, |source| async move {
+        fetch_releases_from(client, &source, token).await
+    })
+    .await?;
+
+    log::info!("Firmware catalog served by {}", source.name);
+
+    Ok(releases)
+}
+
+fn catalog_path(state_guard: &AppStateData) -> Option<PathBuf> {
+    Some(
+        state_guard
+            .cache_dir
+            .as_ref()?
+            .as_path()?
+            .join(CATALOG_FILE_NAME),
+    )
+}
+
+pub fn save_catalog(path: &Path, catalog: &FirmwareCatalog) -> anyhow::Result<()> {
+    write_atomic(path, serde_json::to_string(catalog)?.as_bytes())?;
+
+    Ok(())
+}
+
+pub fn load_catalog(path: &Path) -> anyhow::Result<FirmwareCatalog> {
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+/// Returns the catalog, fetching it again if the cached copy is stale or `refresh` is set.
+///
+/// When GitHub can't be reached, the last catalog fetched successfully is served instead
+/// and the app is marked offline until a fetch succeeds again. Errors GitHub answers
+/// with, rate limits included, are returned as they are.
+pub async fn get_catalog(
+    app_handle: &AppHandle,
+    refresh: bool,
+) -> Result<Vec<FirmwareRelease>, CatalogError> {
+    let state = app_handle.state::<AppState>();
+
+    let (sources, token) = {
+        let state_guard = state.lock().await;
+
+        if let Some(catalog) = &state_guard.catalog {
+            if !refresh && catalog.is_fresh(state_guard.offline) {
+                return Ok(catalog.releases.clone());
+            }
+        }
+
+        (
+            state_guard.sources.sources.clone(),
+            state_guard.github_token.clone(),
+        )
+    };
+
+    let client = app_handle.state::<GitHubClient>();
+
+    let fetched = fetch_releases(&client, &sources, token.as_deref()).await;
+
+    let mut state_guard = state.lock().await;
+
+    let path = catalog_path(&state_guard);
+
+    let releases = match fetched {
+        Ok(releases) => {
+            let now = chrono::Utc::now().timestamp_millis();
+
+            let catalog = FirmwareCatalog {
+                checked_at: now,
+                fetched_at: now,
+                releases: releases.clone(),
+            };
+
+            if let Some(path) = &path {
+                if let Err(error) = save_catalog(path, &catalog) {
+                    log::warn!("Unable to persist firmware catalog: {}", error);
+                }
+            }
+
+            state_guard.catalog = Some(catalog);
+            state_guard.offline = false;
+
+            releases
+        }
+        Err(error) if !error.is_offline() => return Err(error),
+        Err(error) => {
+            let last_known = state_guard.catalog.clone().or_else(|| {
+                path.as_deref().and_then(|path| {
+                    load_catalog(path)
+                        .inspect_err(|error| log::info!("No persisted catalog: {}", error))
+                        .ok()
+                })
+            });
+
+            let Some(mut catalog) = last_known else {
+                return Err(error);
+            };
+
+            catalog.checked_at = chrono::Utc::now().timestamp_millis();
+
+            log::warn!(
+                "Serving firmware catalog from {} while offline: {}",
+                catalog.fetched_at,
+                error
+            );
+
+            let releases = catalog.releases.clone();
+
+            state_guard.catalog = Some(catalog);
+            state_guard.offline = true;
+
+            releases
+        }
+    };
+
+    let _ = state_guard.emit_device_state_update(app_handle);
+
+    Ok(releases)
+}
+
+/// Looks up a version in the catalog and checks that it has an archive to download.
+pub async fn find_release(
+    app_handle: &AppHandle,
+    version: &M8Version,
+) -> Result<FirmwareRelease, CatalogError> {
+    let releases = get_catalog(app_handle, false).await?;
+
+    let release = releases
+        .into_iter()
+        .find(|release| &release.version == version)
+        .ok_or_else(|| CatalogError::UnknownVersion(version.to_string()))?;
+
+    if release.download_url.is_none() {
+        return Err(CatalogError::NoArchive(version.to_string()));
+    }
+
+    Ok(release)
+}
+
+#[tauri::command]
+pub async fn get_firmware_catalog(
+    app_handle: AppHandle,
+    refresh: Option<bool>,
+) -> Result<Vec<FirmwareRelease>, CatalogError> {
+    get_catalog(&app_handle, refresh.unwrap_or_default()).await
+}
+
+/// Makes `version` the firmware to flash: the local archive at `path`, or the catalog
+/// release when `path` is empty. Remote versions are resolved before this returns, so a
+/// flash can't start against a selection that is still being looked up.
+#[tauri::command]
+pub async fn select_version(
+    app_handle: AppHandle,
+    path: String,
+    version: Option<String>,
+) -> Result<(), CatalogError> {
+    log::info!("Version selected: {:?} at {:?}", version, path);
+
+    let parsed = version
+        .as_deref()
+        .and_then(|version| version.parse::<M8Version>().ok());
+
+    let state = app_handle.state::<AppState>();
+
+    {
+        // Nothing is flashed until the new selection is resolved
+        let mut state_guard = state.lock().await;
+
+        state_guard.archive_source = ArchiveSource::None;
+        state_guard.expected_archive = None;
+        state_guard.size = 0;
+        state_guard.version = parsed.clone();
+    }
+
+    let (archive_source, size, expected_archive) = if path.is_empty() {
+        let parsed = parsed
+            .as_ref()
+            .ok_or_else(|| CatalogError::UnknownVersion(version.unwrap_or_default()))?;
+
+        let release = find_release(&app_handle, parsed).await?;
+
+        (
+            ArchiveSource::RemoteUrl(release.download_url.unwrap_or_default()),
+            release.size,
+            release.sha.map(|sha| ExpectedArchive {
+                sha,
+                size: release.size,
+            }),
+        )
+    } else {
+        let path = PathBuf::from(path);
+
+        // The picker can only filter on the last extension, so `.gz` lets any gzip through
+        if !is_hex(&path) && ArchiveFormat::from_path(&path).is_none() {
+            return Err(CatalogError::UnsupportedFile(path.display().to_string()));
+        }
+
+        (ArchiveSource::LocalPath(path), 0, None)
+    };
+
+    let mut state_guard = state.lock().await;
+
+    state_guard.archive_source = archive_source;
+    state_guard.expected_archive = expected_archive;
+    state_guard.size = size;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, path: &str) -> ContentsEntry {
+        ContentsEntry {
+            name: name.into(),
+            path: path.into(),
+            sha: "abc".into(),
+            size: 42,
+        }
+    }
+
+    #[test]
+    fn persisted_catalog_round_trips() {
+        let path = std::env::temp_dir().join(format!("m8-catalog-{}.json", std::process::id()));
+
+        let catalog = FirmwareCatalog {
+            checked_at: 1_700_000_000_000,
+            fetched_at: 1_700_000_000_000,
+            releases: merge_releases(
+                parse_changelog("2024-01-03 - Version 4.0.1\n- Fix: a\n"),
+                vec![(
+                    "4.0.1".parse().unwrap(),
+                    entry("M8Firmware.zip", "M8Firmware.zip"),
+                )],
+                &FirmwareSource::github(),
+            ),
+        };
+
+        save_catalog(&path, &catalog).unwrap();
+        assert_eq!(load_catalog(&path).unwrap(), catalog);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn merge_assigns_archive_to_latest_patch() {
+        let changelog = parse_changelog(
+            "2024-01-03 - Version 4.0.1\n- Fix: a\n\n2024-01-02 - Version 4.0.0A\n\n2024-01-01 - Version 4.0.0\n",
+        );
+
+        let releases = merge_releases(
+            changelog,
+            vec![
+                (
+                    "4.0.0".parse().unwrap(),
+                    entry("M8Firmware_V4_0_0.zip", "Releases/M8Firmware_V4_0_0.zip"),
+                ),
+                (
+                    "3.9.0".parse().unwrap(),
+                    entry("M8Firmware_V3_9_0.zip", "Releases/M8Firmware_V3_9_0.zip"),
+                ),
+            ],
+            &FirmwareSource::github(),
+        );
+
+        let patched = releases
+            .iter()
+            .find(|r| r.version.to_string() == "4.0.0A")
+            .unwrap();
+        assert_eq!(patched.path, "Releases/M8Firmware_V4_0_0.zip");
+        assert_eq!(
+            patched.download_url.as_deref(),
+            Some("https://api.github.com/repos/Dirtywave/M8Firmware/contents/Releases/M8Firmware_V4_0_0.zip")
+        );
+
+        let base = releases
+            .iter()
+            .find(|r| r.version.to_string() == "4.0.0")
+            .unwrap();
+        assert!(base.download_url.is_none());
+
+        // Archives missing from the changelog are kept
+        let orphan = releases
+            .iter()
+            .find(|r| r.version.to_string() == "3.9.0")
+            .unwrap();
+        assert_eq!(orphan.date, None);
+        assert_eq!(orphan.size, 42);
+
+        // Sorted newest first by version, not by string
+        assert_eq!(releases[0].version.to_string(), "4.0.1");
+        assert_eq!(releases.last().unwrap().version.to_string(), "3.9.0");
+    }
+
+    #[test]
+    fn only_unreachable_servers_count_as_offline() {
+        let unreachable = tauri::async_runtime::block_on(
+            reqwest::Client::new().get("http://127.0.0.1:9/").send(),
+        )
+        .unwrap_err();
+        assert!(CatalogError::Request(unreachable).is_offline());
+
+        assert!(!CatalogError::Status {
+            body: String::new(),
+            status: 503,
+        }
+        .is_offline());
+        assert!(!CatalogError::RateLimited {
+            until: chrono::Local::now(),
+        }
+        .is_offline());
+        assert!(!CatalogError::Parse(serde_json::from_str::<u8>("").unwrap_err()).is_offline());
+    }
+}
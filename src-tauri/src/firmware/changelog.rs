@@ -0,0 +1,339 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// A changelog version block starts with a line like `2024-06-04 - Version 4.0.1`
+const VERSION_HEADER_PATTERN: &str = r"^(?<date>\d{4}-\d\d-\d\d) - Version (?<version>.+)$";
+
+const ENTRY_PATTERN: &str = r"^(?<type>Fix|Improved|New)[:;] (?<description>.*)$";
+
+// Lines like `Sampler: new slice mode` are entries even without the leading dash
+const BARE_ENTRY_PATTERN: &str = r"^\w+: .*$";
+
+const ENTRY_PREFIX: &str = "- ";
+
+const DETAIL_PREFIX: &str = "-   ";
+
+// Written as `- Fix from 3.2.1 ...`, these lack the colon the other entry types carry
+const FIX_FROM_PREFIX: &str = "Fix from ";
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangelogEntryType {
+    Change,
+    Fix,
+    Improved,
+    New,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ChangelogEntry {
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Vec<String>>,
+    #[serde(rename = "type")]
+    pub entry_type: ChangelogEntryType,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ChangelogSection {
+    pub entries: Vec<ChangelogEntry>,
+    pub id: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// A single version block of `changelog.txt`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ChangelogVersion {
+    pub changelog: Vec<ChangelogSection>,
+    pub date: String,
+    pub version: String,
+}
+
+pub struct ChangelogParser {
+    bare_entry: Regex,
+    entry: Regex,
+    version_header: Regex,
+}
+
+impl Default for ChangelogParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChangelogParser {
+    pub fn new() -> Self {
+        Self {
+            bare_entry: Regex::new(BARE_ENTRY_PATTERN).unwrap(),
+            entry: Regex::new(ENTRY_PATTERN).unwrap(),
+            version_header: Regex::new(VERSION_HEADER_PATTERN).unwrap(),
+        }
+    }
+
+    pub fn is_version_header(&self, line: &str) -> bool {
+        self.version_header.is_match(line)
+    }
+
+    /// Parses the whole changelog, newest version first. Lines before the first
+    /// version header are ignored.
+    pub fn parse(&self, changelog: &str) -> Vec<ChangelogVersion> {
+        let mut blocks: Vec<Vec<&str>> = Vec::new();
+
+        for line in changelog.lines().map(str::trim_end) {
+            if self.is_version_header(line) {
+                blocks.push(Vec::new());
+            }
+
+            if let Some(block) = blocks.last_mut() {
+                block.push(line);
+            }
+        }
+
+        blocks
+            .iter()
+            .filter_map(|block| self.parse_version(block))
+            .collect()
+    }
+
+    /// Parses one version block. The first line must be the version header.
+    pub fn parse_version(&self, block: &[&str]) -> Option<ChangelogVersion> {
+        let (header, lines) = block.split_first()?;
+
+        let captures = self.version_header.captures(header)?;
+
+        let mut builder = SectionBuilder::default();
+
+        for line in lines.iter().filter(|line| !line.trim().is_empty()) {
+            if let Some(detail) = line.strip_prefix(DETAIL_PREFIX) {
+                builder.push_detail(detail.trim_start());
+            } else if let Some(entry) = line.strip_prefix(ENTRY_PREFIX) {
+                builder.push_entry(self.parse_entry(entry));
+            } else if self.bare_entry.is_match(line) {
+                builder.push_entry(self.parse_entry(line));
+            } else {
+                builder.push_section(line);
+            }
+        }
+
+        Some(ChangelogVersion {
+            changelog: builder.finish(),
+            date: captures["date"].to_owned(),
+            version: captures["version"].to_owned(),
+        })
+    }
+
+    fn parse_entry(&self, text: &str) -> ChangelogEntry {
+        if let Some(description) = text.strip_prefix(FIX_FROM_PREFIX) {
+            return ChangelogEntry {
+                description: format!("from {}", description),
+                details: None,
+                entry_type: ChangelogEntryType::Fix,
+            };
+        }
+
+        match self.entry.captures(text) {
+            Some(captures) => ChangelogEntry {
+                description: captures["description"].to_owned(),
+                details: None,
+                entry_type: match &captures["type"] {
+                    "Fix" => ChangelogEntryType::Fix,
+                    "Improved" => ChangelogEntryType::Improved,
+                    _ => ChangelogEntryType::New,
+                },
+            },
+            None => ChangelogEntry {
+                description: text.to_owned(),
+                details: None,
+                entry_type: ChangelogEntryType::Change,
+            },
+        }
+    }
+}
+
+#[derive(Default)]
+struct SectionBuilder {
+    current_entry: Option<ChangelogEntry>,
+    current_section: Option<ChangelogSection>,
+    sections: Vec<ChangelogSection>,
+}
+
+impl SectionBuilder {
+    fn section(&mut self) -> &mut ChangelogSection {
+        let id = self.sections.len() as u32;
+
+        self.current_section
+            .get_or_insert_with(|| ChangelogSection {
+                entries: Vec::new(),
+                id,
+                title: None,
+            })
+    }
+
+    fn flush_entry(&mut self) {
+        if let Some(entry) = self.current_entry.take() {
+            self.section().entries.push(entry);
+        }
+    }
+
+    fn flush_section(&mut self) {
+        self.flush_entry();
+
+        if let Some(section) = self.current_section.take() {
+            // An untitled section only exists to hold entries preceding the first title
+            if section.title.is_some() || !section.entries.is_empty() {
+                self.sections.push(section);
+            }
+        }
+    }
+
+    fn push_detail(&mut self, detail: &str) {
+        match self.current_entry.as_mut() {
+            Some(entry) => entry
+                .details
+                .get_or_insert_with(Vec::new)
+                .push(detail.to_owned()),
+            None => {
+                log::warn!("Changelog detail line without an entry: {}", detail);
+
+                self.push_entry(ChangelogEntry {
+                    description: detail.to_owned(),
+                    details: None,
+                    entry_type: ChangelogEntryType::Change,
+                });
+            }
+        }
+    }
+
+    fn push_entry(&mut self, entry: ChangelogEntry) {
+        self.flush_entry();
+
+        self.current_entry = Some(entry);
+    }
+
+    fn push_section(&mut self, title: &str) {
+        self.flush_section();
+
+        let id = self.sections.len() as u32;
+
+        self.current_section = Some(ChangelogSection {
+            entries: Vec::new(),
+            id,
+            title: Some(title.to_owned()),
+        });
+    }
+
+    fn finish(mut self) -> Vec<ChangelogSection> {
+        self.flush_section();
+
+        self.sections
+    }
+}
+
+pub fn parse_changelog(changelog: &str) -> Vec<ChangelogVersion> {
+    ChangelogParser::new().parse(changelog)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::{Path, PathBuf};
+
+    fn fixture_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/changelog")
+            .join(name)
+    }
+
+    /// Compares the parsed changelog against its `.json` golden file. Run with
+    /// `UPDATE_GOLDEN=1` to write the golden file for a new snapshot, or to rewrite it
+    /// after an intended change.
+    fn assert_golden(input_path: &Path) {
+        let input = std::fs::read_to_string(input_path).unwrap();
+        let golden_path = input_path.with_extension("json");
+
+        let actual = serde_json::to_string_pretty(&parse_changelog(&input)).unwrap() + "\n";
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&golden_path, &actual).unwrap();
+        }
+
+        let expected = std::fs::read_to_string(&golden_path)
+            .unwrap_or_else(|_| panic!("no golden file for {:?}", input_path));
+
+        assert_eq!(actual, expected, "golden mismatch for {:?}", input_path);
+    }
+
+    /// Every `.txt` in the fixture directory: snapshots of Dirtywave's changelog.txt,
+    /// named `changelog-<date>.txt`, and hand-written `synthetic-*.txt` edge cases.
+    #[test]
+    fn golden_changelogs() {
+        let mut inputs: Vec<PathBuf> = std::fs::read_dir(fixture_path(""))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+            .collect();
+
+        inputs.sort();
+
+        assert!(!inputs.is_empty());
+
+        for input in inputs {
+            assert_golden(&input);
+        }
+    }
+
+    #[test]
+    fn fix_from_lines_are_fixes() {
+        let parsed = parse_changelog(
+            "2023-01-01 - Version 2.7.8A\n- Fix from 2.7.8: crash when saving a project\n",
+        );
+
+        let entry = &parsed[0].changelog[0].entries[0];
+        assert_eq!(entry.entry_type, ChangelogEntryType::Fix);
+        assert_eq!(entry.description, "from 2.7.8: crash when saving a project");
+    }
+
+    #[test]
+    fn entry_type_only_matches_at_start() {
+        let parsed = parse_changelog("2023-01-01 - Version 3.0.0\n- Sampler - New: nope\n");
+
+        let entry = &parsed[0].changelog[0].entries[0];
+        assert_eq!(entry.entry_type, ChangelogEntryType::Change);
+        assert_eq!(entry.description, "Sampler - New: nope");
+    }
+
+    #[test]
+    fn last_entry_stays_in_its_section() {
+        let parsed = parse_changelog(
+            "2023-01-01 - Version 3.0.0\nMixer\n- Fix: a\nSampler\n- New: b\n-   detail\n",
+        );
+
+        let sections = &parsed[0].changelog;
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].title.as_deref(), Some("Mixer"));
+        assert_eq!(sections[0].entries[0].description, "a");
+        assert_eq!(sections[1].entries[0].description, "b");
+        assert_eq!(
+            sections[1].entries[0].details,
+            Some(vec!["detail".to_owned()])
+        );
+    }
+
+    #[test]
+    fn serializes_to_frontend_shape() {
+        let parsed = parse_changelog("2023-01-01 - Version 3.0.0\n- Improved: speed\n");
+
+        assert_eq!(
+            serde_json::to_value(&parsed[0]).unwrap(),
+            serde_json::json!({
+                "changelog": [
+                    { "entries": [{ "description": "speed", "type": "improved" }], "id": 0 }
+                ],
+                "date": "2023-01-01",
+                "version": "3.0.0",
+            })
+        );
+    }
+}
@@ -0,0 +1,422 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    future::Future,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use reqwest::{
+    header::{HeaderMap, CONTENT_LENGTH, RANGE},
+    StatusCode,
+};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+pub const MAX_CONCURRENT_DOWNLOADS: usize = 2;
+
+const MAX_ATTEMPTS: u32 = 3;
+
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+const PROGRESS_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+// Appended to the destination file name while a download is in flight
+pub const PARTIAL_SUFFIX: &str = ".part";
+
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadError {
+    #[error("Download cancelled")]
+    Cancelled,
+    #[error("Connection failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("Server responded with status {0}")]
+    Status(u16),
+    #[error("Unable to write download: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl DownloadError {
+    /// Whether another attempt, resuming from the partial file, might succeed.
+    fn is_retryable(&self) -> bool {
+        match self {
+            DownloadError::Cancelled => false,
+            DownloadError::Request(_) | DownloadError::Io(_) => true,
+            DownloadError::Status(status) => {
+                *status == StatusCode::RANGE_NOT_SATISFIABLE.as_u16() || *status >= 500
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DownloadProgress {
+    pub bytes_downloaded: u64,
+    // Zero when the server doesn't say
+    pub size: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct DownloadRequest {
+    pub destination: PathBuf,
+    pub headers: HeaderMap,
+    pub url: String,
+}
+
+/// Runs archive downloads with resume, cancellation and a cap on how many run at once.
+///
+/// Bytes land in `<destination>.part`, which is renamed to the destination once the
+/// transfer completes. A dropped connection resumes from the partial file with an HTTP
+/// Range request; cancellation or a non-retryable failure deletes it, unless a newer
+/// download of the same destination took over.
+pub struct DownloadManager {
+    // Keyed by destination, with the id of the download that owns it
+    active: Mutex<HashMap<PathBuf, (u64, CancellationToken)>>,
+    client: reqwest::Client,
+    next_id: AtomicU64,
+    permits: Semaphore,
+}
+
+pub fn partial_path(destination: &Path) -> PathBuf {
+    let mut name = destination.file_name().unwrap_or_default().to_owned();
+    name.push(PARTIAL_SUFFIX);
+
+    destination.with_file_name(name)
+}
+
+impl DownloadManager {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            active: Mutex::new(HashMap::new()),
+            client: reqwest::Client::new(),
+            next_id: AtomicU64::new(0),
+            permits: Semaphore::new(max_concurrent),
+        }
+    }
+
+    pub fn cancel_all(&self) {
+        let active = self.active.lock().unwrap();
+
+        log::info!("Cancelling {} download(s)", active.len());
+
+        for (_, token) in active.values() {
+            token.cancel();
+        }
+    }
+
+    /// Cancels the download writing to `destination`, if there is one.
+    pub fn cancel(&self, destination: &Path) {
+        if let Some((_, token)) = self.active.lock().unwrap().get(destination) {
+            token.cancel();
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.active.lock().unwrap().is_empty()
+    }
+
+    /// The size the server reports for `request`, without downloading it.
+    pub async fn content_length(
+        &self,
+        request: &DownloadRequest,
+    ) -> Result<Option<u64>, DownloadError> {
+        let response = self
+            .client
+            .head(&request.url)
+            .headers(request.headers.clone())
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(DownloadError::Status(response.status().as_u16()));
+        }
+
+        // reqwest reports the (empty) body length for HEAD, so read the header itself
+        Ok(response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok()))
+    }
+
+    pub async fn download<F, Fut>(
+        &self,
+        request: DownloadRequest,
+        mut on_progress: F,
+    ) -> Result<PathBuf, DownloadError>
+    where
+        F: FnMut(DownloadProgress) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let token = CancellationToken::new();
+
+        // Only one download may write a given file
+        if let Some((_, previous)) = self
+            .active
+            .lock()
+            .unwrap()
+            .insert(request.destination.clone(), (id, token.clone()))
+        {
+            previous.cancel();
+        }
+
+        let result = tokio::select! {
+            permit = self.permits.acquire() => match permit {
+                Ok(_permit) => self.run(&request, &token, &mut on_progress).await,
+                Err(_) => Err(DownloadError::Cancelled),
+            },
+            _ = token.cancelled() => Err(DownloadError::Cancelled),
+        };
+
+        // A download that was replaced leaves the entry and the partial file to its successor
+        let replaced = {
+            let mut active = self.active.lock().unwrap();
+
+            match active.get(&request.destination) {
+                Some((owner, _)) if *owner == id => {
+                    active.remove(&request.destination);
+
+                    false
+                }
+                _ => true,
+            }
+        };
+
+        if let Err(error) = &result {
+            if !error.is_retryable() && !replaced {
+                let _ = fs::remove_file(partial_path(&request.destination));
+            }
+        }
+
+        result
+    }
+
+    async fn run<F, Fut>(
+        &self,
+        request: &DownloadRequest,
+        token: &CancellationToken,
+        on_progress: &mut F,
+    ) -> Result<PathBuf, DownloadError>
+    where
+        F: FnMut(DownloadProgress) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let partial = partial_path(&request.destination);
+
+        let mut attempt = 1;
+
+        loop {
+            match self.attempt(request, &partial, token, on_progress).await {
+                Ok(()) => break,
+                Err(error) if error.is_retryable() && attempt < MAX_ATTEMPTS => {
+                    log::warn!(
+                        "Download attempt {} of {} failed, resuming: {}",
+                        attempt,
+                        request.url,
+                        error
+                    );
+
+                    attempt += 1;
+
+                    tokio::select! {
+                        _ = tokio::time::sleep(RETRY_DELAY) => {}
+                        _ = token.cancelled() => return Err(DownloadError::Cancelled),
+                    }
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        fs::rename(&partial, &request.destination)?;
+
+        Ok(request.destination.clone())
+    }
+
+    async fn attempt<F, Fut>(
+        &self,
+        request: &DownloadRequest,
+        partial: &Path,
+        token: &CancellationToken,
+        on_progress: &mut F,
+    ) -> Result<(), DownloadError>
+    where
+        F: FnMut(DownloadProgress) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let offset = fs::metadata(partial).map(|meta| meta.len()).unwrap_or(0);
+
+        let mut builder = self
+            .client
+            .get(&request.url)
+            .headers(request.headers.clone());
+
+        if offset > 0 {
+            log::info!("Resuming {} from byte {}", request.url, offset);
+
+            builder = builder.header(RANGE, format!("bytes={}-", offset));
+        }
+
+        let response = tokio::select! {
+            response = builder.send() => response?,
+            _ = token.cancelled() => return Err(DownloadError::Cancelled),
+        };
+
+        let status = response.status();
+
+        let (mut file, mut bytes_downloaded) = match status {
+            StatusCode::PARTIAL_CONTENT => (OpenOptions::new().append(true).open(partial)?, offset),
+            // The server ignored the range, so start over
+            status if status.is_success() => (File::create(partial)?, 0),
+            status => {
+                if status == StatusCode::RANGE_NOT_SATISFIABLE {
+                    fs::remove_file(partial)?;
+                }
+
+                return Err(DownloadError::Status(status.as_u16()));
+            }
+        };
+
+        let size = response
+            .content_length()
+            .map(|length| length + bytes_downloaded)
+            .unwrap_or_default();
+
+        on_progress(DownloadProgress {
+            bytes_downloaded,
+            size,
+        })
+        .await;
+
+        let mut stream = response.bytes_stream();
+
+        let mut last_progress_update = Instant::now();
+
+        loop {
+            let chunk = tokio::select! {
+                chunk = stream.next() => chunk,
+                _ = token.cancelled() => return Err(DownloadError::Cancelled),
+            };
+
+            let Some(chunk) = chunk else {
+                break;
+            };
+
+            let bytes = chunk?;
+
+            file.write_all(&bytes)?;
+
+            bytes_downloaded += bytes.len() as u64;
+
+            if last_progress_update.elapsed() >= PROGRESS_UPDATE_INTERVAL {
+                on_progress(DownloadProgress {
+                    bytes_downloaded,
+                    size,
+                })
+                .await;
+
+                last_progress_update = Instant::now();
+            }
+        }
+
+        file.sync_all()?;
+
+        on_progress(DownloadProgress {
+            bytes_downloaded,
+            size,
+        })
+        .await;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_file_sits_next_to_destination() {
+        assert_eq!(
+            partial_path(Path::new("/tmp/4_0_1.zip")),
+            PathBuf::from("/tmp/4_0_1.zip.part")
+        );
+    }
+
+    #[test]
+    fn cancelled_downloads_remove_partial_files() {
+        let destination =
+            std::env::temp_dir().join(format!("m8-download-{}.zip", std::process::id()));
+        fs::write(partial_path(&destination), b"half").unwrap();
+
+        let manager = DownloadManager::new(1);
+
+        // With the only permit taken, the download waits until it is cancelled
+        let _permit = manager.permits.try_acquire().unwrap();
+
+        let download = manager.download(
+            DownloadRequest {
+                destination: destination.clone(),
+                headers: HeaderMap::new(),
+                url: "http://127.0.0.1:9/unreachable.zip".into(),
+            },
+            |_| async {},
+        );
+
+        let (result, _) = tauri::async_runtime::block_on(async {
+            tokio::join!(download, async {
+                while !manager.is_active() {
+                    tokio::task::yield_now().await;
+                }
+
+                manager.cancel_all();
+            })
+        });
+
+        assert!(matches!(result, Err(DownloadError::Cancelled)));
+        assert!(!partial_path(&destination).exists());
+    }
+
+    #[test]
+    fn replaced_downloads_leave_their_successor_alone() {
+        let destination =
+            std::env::temp_dir().join(format!("m8-replaced-{}.zip", std::process::id()));
+        fs::write(partial_path(&destination), b"half").unwrap();
+
+        let manager = DownloadManager::new(1);
+
+        let _permit = manager.permits.try_acquire().unwrap();
+
+        let request = DownloadRequest {
+            destination: destination.clone(),
+            headers: HeaderMap::new(),
+            url: "http://127.0.0.1:9/unreachable.zip".into(),
+        };
+
+        let first = manager.download(request.clone(), |_| async {});
+        let second = manager.download(request, |_| async {});
+
+        let (first, second, _) = tauri::async_runtime::block_on(async {
+            tokio::join!(first, second, async {
+                // Polled in order, so the second download has replaced the first by now
+                tokio::task::yield_now().await;
+
+                assert!(manager.is_active());
+                assert!(partial_path(&destination).exists());
+
+                manager.cancel_all();
+            })
+        });
+
+        assert!(matches!(first, Err(DownloadError::Cancelled)));
+        assert!(matches!(second, Err(DownloadError::Cancelled)));
+        assert!(!manager.is_active());
+        assert!(!partial_path(&destination).exists());
+    }
+}
@@ -0,0 +1,287 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Local, TimeZone};
+use reqwest::{
+    header::{HeaderMap, ETAG, IF_NONE_MATCH, RETRY_AFTER},
+    StatusCode,
+};
+use tauri::{AppHandle, Manager};
+use tauri_plugin_store::StoreExt;
+
+use crate::{
+    firmware::{
+        catalog::CatalogError,
+        sources::{FirmwareSource, SETTINGS_STORE},
+    },
+    state::AppState,
+};
+
+const TOKEN_KEY: &str = "githubToken";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The `X-RateLimit-*` headers GitHub attaches to every API response.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RateLimit {
+    pub limit: u32,
+    pub remaining: u32,
+    // Unix seconds
+    pub reset: i64,
+}
+
+impl RateLimit {
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name: &str| -> Option<i64> { headers.get(name)?.to_str().ok()?.parse().ok() };
+
+        Some(Self {
+            limit: u32::try_from(header("x-ratelimit-limit")?).ok()?,
+            remaining: u32::try_from(header("x-ratelimit-remaining")?).ok()?,
+            reset: header("x-ratelimit-reset")?,
+        })
+    }
+
+    pub fn reset_time(&self) -> DateTime<Local> {
+        Local
+            .timestamp_opt(self.reset, 0)
+            .single()
+            .unwrap_or_else(Local::now)
+    }
+}
+
+/// When a rejected request may be repeated, if the response says it was rate limited.
+///
+/// The primary limit reports an exhausted `X-RateLimit-Remaining`; secondary limits
+/// send `Retry-After` instead.
+pub fn rate_limited_until(status: StatusCode, headers: &HeaderMap) -> Option<DateTime<Local>> {
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    let retry_after = headers
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok()?.parse::<i64>().ok());
+
+    if let Some(seconds) = retry_after {
+        return Some(Local::now() + chrono::Duration::seconds(seconds));
+    }
+
+    RateLimit::from_headers(headers)
+        .filter(|limit| limit.remaining == 0)
+        .map(|limit| limit.reset_time())
+}
+
+/// Pulls GitHub's `message` out of an error response, which is otherwise a JSON document.
+fn error_message(body: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|value| value.get("message")?.as_str().map(str::to_owned))
+        .unwrap_or_else(|| body.to_owned())
+}
+
+struct CachedResponse {
+    body: String,
+    etag: String,
+}
+
+/// HTTP client for the contents API that revalidates with `If-None-Match`.
+///
+/// A `304 Not Modified` doesn't count against the rate limit, so repeated catalog
+/// refreshes are nearly free once the responses have been seen.
+pub struct GitHubClient {
+    client: reqwest::Client,
+    responses: Mutex<HashMap<String, CachedResponse>>,
+}
+
+impl GitHubClient {
+    pub fn new() -> reqwest::Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+            responses: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub async fn get_contents(
+        &self,
+        source: &FirmwareSource,
+        token: Option<&str>,
+        path: &str,
+        accept: &str,
+    ) -> Result<String, CatalogError> {
+        let url = source.url(path);
+
+        // The media type changes the body, so it is part of the key
+        let key = format!("{} {}", accept, url);
+
+        let mut headers = source.headers(accept, token)?;
+
+        if let Some(cached) = self.responses.lock().unwrap().get(&key) {
+            headers.insert(IF_NONE_MATCH, cached.etag.parse()?);
+        }
+
+        let response = self.client.get(&url).headers(headers).send().await?;
+
+        let status = response.status();
+        let response_headers = response.headers().clone();
+
+        if let Some(limit) = RateLimit::from_headers(&response_headers) {
+            log::debug!(
+                "{} of {} GitHub requests left until {}",
+                limit.remaining,
+                limit.limit,
+                limit.reset_time().format("%H:%M")
+            );
+        }
+
+        if status == StatusCode::NOT_MODIFIED {
+            if let Some(cached) = self.responses.lock().unwrap().get(&key) {
+                log::info!("{} not modified", url);
+
+                return Ok(cached.body.clone());
+            }
+        }
+
+        if let Some(until) = rate_limited_until(status, &response_headers) {
+            return Err(CatalogError::RateLimited { until });
+        }
+
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            return Err(CatalogError::Status {
+                body: error_message(&body),
+                status: status.as_u16(),
+            });
+        }
+
+        if let Some(etag) = response_headers
+            .get(ETAG)
+            .and_then(|value| value.to_str().ok())
+        {
+            self.responses.lock().unwrap().insert(
+                key,
+                CachedResponse {
+                    body: body.clone(),
+                    etag: etag.to_owned(),
+                },
+            );
+        }
+
+        Ok(body)
+    }
+}
+
+/// Reads the personal access token the user configured, if any.
+pub fn load_token(app_handle: &AppHandle) -> Option<String> {
+    app_handle
+        .store(SETTINGS_STORE)
+        .ok()?
+        .get(TOKEN_KEY)?
+        .as_str()
+        .filter(|token| !token.is_empty())
+        .map(str::to_owned)
+}
+
+/// Stores a GitHub token used for API requests, or removes it when `token` is empty.
+#[tauri::command]
+pub async fn set_github_token(
+    app_handle: AppHandle,
+    token: Option<String>,
+) -> Result<(), CatalogError> {
+    let token = token
+        .map(|token| token.trim().to_owned())
+        .filter(|token| !token.is_empty());
+
+    let store = app_handle
+        .store(SETTINGS_STORE)
+        .map_err(|error| CatalogError::Store(error.to_string()))?;
+
+    match &token {
+        Some(token) => store.set(TOKEN_KEY, token.clone()),
+        None => {
+            store.delete(TOKEN_KEY);
+        }
+    }
+
+    store
+        .save()
+        .map_err(|error| CatalogError::Store(error.to_string()))?;
+
+    let state = app_handle.state::<AppState>();
+
+    state.lock().await.github_token = token;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn has_github_token(app_handle: AppHandle) -> bool {
+    let state = app_handle.state::<AppState>();
+
+    let has_token = state.lock().await.github_token.is_some();
+
+    has_token
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+
+        headers
+    }
+
+    #[test]
+    fn detects_exhausted_rate_limit() {
+        let exhausted = headers(&[
+            ("x-ratelimit-limit", "60"),
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "1700000000"),
+        ]);
+
+        assert_eq!(
+            RateLimit::from_headers(&exhausted),
+            Some(RateLimit {
+                limit: 60,
+                remaining: 0,
+                reset: 1_700_000_000,
+            })
+        );
+        assert_eq!(
+            rate_limited_until(StatusCode::FORBIDDEN, &exhausted).map(|until| until.timestamp()),
+            Some(1_700_000_000)
+        );
+
+        // A 403 with requests to spare is a permissions problem, not a rate limit
+        let remaining = headers(&[
+            ("x-ratelimit-limit", "60"),
+            ("x-ratelimit-remaining", "12"),
+            ("x-ratelimit-reset", "1700000000"),
+        ]);
+        assert_eq!(rate_limited_until(StatusCode::FORBIDDEN, &remaining), None);
+
+        assert!(rate_limited_until(
+            StatusCode::TOO_MANY_REQUESTS,
+            &headers(&[("retry-after", "60")])
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn extracts_error_message() {
+        assert_eq!(
+            error_message(
+                r#"{"message":"Not Found","documentation_url":"https://docs.github.com"}"#
+            ),
+            "Not Found"
+        );
+        assert_eq!(error_message("Bad gateway"), "Bad gateway");
+    }
+}
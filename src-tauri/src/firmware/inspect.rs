@@ -0,0 +1,407 @@
+use std::{
+    ffi::OsStr,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::{
+    firmware::{
+        archive::{self, ArchiveError, ArchiveFormat},
+        intel_hex::{self, HexImage},
+        is_hex,
+        storage::{self, StorageError},
+        variant::{self, FirmwareImage, FirmwareVariant, VariantError},
+        version::{M8Version, VersionParseError},
+        ConnectedDevice,
+    },
+    state::AppState,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum InspectError {
+    #[error("Unable to read {path}: {error}")]
+    Io { error: io::Error, path: String },
+    #[error("Invalid firmware archive: {0}")]
+    Archive(#[from] ArchiveError),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Version(#[from] VersionParseError),
+    #[error("Version {0} is not cached")]
+    NotCached(String),
+    #[error("{0} is not a firmware archive or .hex file")]
+    Unsupported(String),
+    #[error("Nothing to inspect")]
+    NoInput,
+}
+
+impl Serialize for InspectError {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_str())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct InspectedEntry {
+    pub embedded_version: Option<M8Version>,
+    // Why the entry can't be flashed; unset for valid images and for other files
+    pub error: Option<String>,
+    pub is_hex: bool,
+    pub name: String,
+    // The image that would be flashed to the connected device
+    pub selected: bool,
+    pub size: u64,
+    pub variant: Option<FirmwareVariant>,
+    // Doubts about a valid image that don't stop it being flashed
+    pub warnings: Vec<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ArchiveInspection {
+    pub entries: Vec<InspectedEntry>,
+    // Problems with the archive as a whole, or with flashing it to the connected device
+    pub error: Option<String>,
+}
+
+/// A file found in an archive, read into memory rather than extracted.
+struct RawEntry {
+    // Unset for files that aren't firmware images
+    contents: Option<Result<String, String>>,
+    name: String,
+    size: u64,
+}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> InspectError + '_ {
+    move |error| InspectError::Io {
+        error,
+        path: path.display().to_string(),
+    }
+}
+
+fn read_hex(path: &Path) -> Result<RawEntry, InspectError> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+        .to_owned();
+
+    Ok(RawEntry {
+        contents: Some(fs::read_to_string(path).map_err(|error| error.to_string())),
+        name,
+        size: fs::metadata(path).map_err(io_error(path))?.len(),
+    })
+}
+
+fn read_archive(path: &Path) -> Result<Vec<RawEntry>, InspectError> {
+    let file = File::open(path).map_err(io_error(path))?;
+
+    let mut entries = Vec::new();
+
+    archive::visit_files(file, &mut |entry| {
+        let contents = is_hex(&entry.path).then(|| {
+            let mut text = String::new();
+
+            entry
+                .reader
+                .read_to_string(&mut text)
+                .map(|_| text)
+                .map_err(|error| error.to_string())
+        });
+
+        entries.push(RawEntry {
+            contents,
+            name: entry.path.to_string_lossy().into_owned(),
+            size: entry.size,
+        });
+
+        Ok(())
+    })?;
+
+    Ok(entries)
+}
+
+/// Inspects one hex, also returning its classification as an upload would see it.
+fn inspect_hex(
+    raw: &RawEntry,
+    contents: &Result<String, String>,
+) -> (InspectedEntry, Result<FirmwareImage, VariantError>) {
+    let image = contents
+        .as_ref()
+        .map_err(Clone::clone)
+        .and_then(|text| intel_hex::parse(text).map_err(|error| error.to_string()));
+
+    let variant = variant::classify_parsed(Path::new(&raw.name), image.as_ref().ok());
+
+    let (error, warnings) = match (&image, &variant) {
+        (Err(error), _) => (Some(error.clone()), vec![]),
+        (_, Err(error)) => (Some(error.to_string()), vec![]),
+        (Ok(image), Ok(variant)) => {
+            match image.validate_for_device(&variant.model, variant.version.as_ref()) {
+                Ok(warnings) => (None, warnings.iter().map(ToString::to_string).collect()),
+                Err(error) => (Some(error.to_string()), vec![]),
+            }
+        }
+    };
+
+    let entry = InspectedEntry {
+        embedded_version: image.as_ref().ok().and_then(HexImage::embedded_version),
+        error,
+        is_hex: true,
+        name: raw.name.clone(),
+        selected: false,
+        size: raw.size,
+        variant: variant.as_ref().ok().cloned(),
+        warnings,
+    };
+
+    let classified = variant.map(|variant| FirmwareImage {
+        path: PathBuf::from(&raw.name),
+        variant,
+    });
+
+    (entry, classified)
+}
+
+/// Images are flattened into one folder when cached, so two hex files may not share a name.
+fn duplicate_name(entries: &[InspectedEntry]) -> Option<String> {
+    let mut file_names: Vec<&OsStr> = Vec::new();
+
+    entries
+        .iter()
+        .filter(|entry| entry.is_hex)
+        .filter_map(|entry| Path::new(&entry.name).file_name())
+        .find(|file_name| {
+            let seen = file_names.contains(file_name);
+            file_names.push(file_name);
+            seen
+        })
+        .map(|file_name| {
+            ArchiveError::DuplicateName(file_name.to_string_lossy().into_owned()).to_string()
+        })
+}
+
+fn inspect_entries(raw: Vec<RawEntry>, device: Option<&ConnectedDevice>) -> ArchiveInspection {
+    let mut entries: Vec<InspectedEntry> = Vec::new();
+    let mut classified: Vec<Result<FirmwareImage, VariantError>> = Vec::new();
+
+    for raw in &raw {
+        match &raw.contents {
+            Some(contents) => {
+                let (entry, image) = inspect_hex(raw, contents);
+
+                entries.push(entry);
+                classified.push(image);
+            }
+            None => entries.push(InspectedEntry {
+                embedded_version: None,
+                error: None,
+                is_hex: false,
+                name: raw.name.clone(),
+                selected: false,
+                size: raw.size,
+                variant: None,
+                warnings: vec![],
+            }),
+        }
+    }
+
+    // The same checks an upload makes: any hex it can't classify fails the whole archive
+    let images = classified.into_iter().collect::<Result<Vec<_>, _>>();
+
+    let error = match (duplicate_name(&entries), images) {
+        (Some(duplicate), _) => Some(duplicate),
+        (None, Err(error)) => Some(error.to_string()),
+        (None, Ok(images)) => match (variant::check_images(&images), device) {
+            (Err(error), _) => Some(error.to_string()),
+            (Ok(()), Some(device)) => {
+                let version = images
+                    .iter()
+                    .find_map(|image| image.variant.version.clone());
+
+                match variant::select_for_device(images, device, version.as_ref()) {
+                    Ok(target) => {
+                        for entry in &mut entries {
+                            entry.selected = Path::new(&entry.name) == target.path();
+                        }
+
+                        None
+                    }
+                    Err(error) => Some(error.to_string()),
+                }
+            }
+            (Ok(()), None) => None,
+        },
+    };
+
+    ArchiveInspection { entries, error }
+}
+
+/// Lists what's inside a firmware archive or `.hex` at `path`, or the cached images of `version`,
+/// and which image would be flashed to the connected device. Nothing is extracted.
+#[tauri::command]
+pub async fn inspect_firmware(
+    app_handle: AppHandle,
+    path: Option<String>,
+    version: Option<String>,
+) -> Result<ArchiveInspection, InspectError> {
+    let raw = match (path, version) {
+        (Some(path), _) => {
+            let path = PathBuf::from(path);
+
+            if is_hex(&path) {
+                vec![read_hex(&path)?]
+            } else if ArchiveFormat::from_path(&path).is_some() {
+                read_archive(&path)?
+            } else {
+                return Err(InspectError::Unsupported(path.display().to_string()));
+            }
+        }
+        (None, Some(version)) => {
+            let version = version.parse::<M8Version>()?;
+
+            let cache = storage::lock_cache(&app_handle).await?;
+
+            let entry = cache
+                .entries()
+                .iter()
+                .find(|entry| entry.version.as_ref() == Some(&version))
+                .ok_or_else(|| InspectError::NotCached(version.to_string()))?;
+
+            let dir = cache.entry_dir(&entry.archive_sha256);
+
+            entry
+                .images
+                .iter()
+                .map(|image| read_hex(&dir.join(&image.file_name)))
+                .collect::<Result<_, _>>()?
+        }
+        (None, None) => return Err(InspectError::NoInput),
+    };
+
+    let device = app_handle
+        .state::<AppState>()
+        .lock()
+        .await
+        .device()
+        .cloned();
+
+    Ok(inspect_entries(raw, device.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        firmware::{intel_hex::tests::hex_file, DeviceType},
+        state::tests::sample_device,
+    };
+
+    fn hex_entry(name: &str, contents: String) -> RawEntry {
+        RawEntry {
+            size: contents.len() as u64,
+            contents: Some(Ok(contents)),
+            name: name.into(),
+        }
+    }
+
+    #[test]
+    fn reports_entries_and_selection() {
+        let device = ConnectedDevice {
+            device_type: DeviceType::MODEL02,
+            ..sample_device("123", "123-Teensy")
+        };
+
+        let inspection = inspect_entries(
+            vec![
+                hex_entry("M8_V4_0_1_MODEL01.hex", hex_file(0x6000_0000, &[0xAA; 32])),
+                hex_entry("M8_V4_0_1_MODEL02.hex", hex_file(0x6000_0000, &[0xBB; 32])),
+                // Outside the flash of a Teensy 4.0
+                hex_entry("M8_V4_0_1_HEADLESS.hex", hex_file(0x0000_0000, &[0xCC; 32])),
+                RawEntry {
+                    contents: None,
+                    name: "README.txt".into(),
+                    size: 7,
+                },
+            ],
+            Some(&device),
+        );
+
+        assert_eq!(inspection.error, None);
+
+        let selected: Vec<&str> = inspection
+            .entries
+            .iter()
+            .filter(|entry| entry.selected)
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(selected, vec!["M8_V4_0_1_MODEL02.hex"]);
+
+        assert!(inspection.entries[2].error.is_some());
+        assert!(!inspection.entries[3].is_hex);
+
+        // Without a MODEL02 image, the reason is reported for the whole archive
+        let inspection = inspect_entries(
+            vec![hex_entry(
+                "M8_V4_0_1_MODEL01.hex",
+                hex_file(0x6000_0000, &[0xAA; 32]),
+            )],
+            Some(&device),
+        );
+        assert!(inspection.error.unwrap().contains("MODEL02"));
+
+        let inspection = inspect_entries(
+            vec![
+                hex_entry(
+                    "a/M8_V4_0_1_MODEL02.hex",
+                    hex_file(0x6000_0000, &[0xAA; 32]),
+                ),
+                hex_entry(
+                    "b/M8_V4_0_1_MODEL02.hex",
+                    hex_file(0x6000_0000, &[0xBB; 32]),
+                ),
+            ],
+            None,
+        );
+        assert!(inspection
+            .error
+            .unwrap()
+            .contains("more than one file named"));
+    }
+
+    #[test]
+    fn unclassifiable_hex_fails_the_archive() {
+        let device = ConnectedDevice {
+            device_type: DeviceType::MODEL02,
+            ..sample_device("123", "123-Teensy")
+        };
+
+        let inspection = inspect_entries(
+            vec![
+                hex_entry("M8_V4_0_0_MODEL02.hex", hex_file(0x6000_0000, &[0xAA; 32])),
+                hex_entry("M8_V4_0_0_MODEL03.hex", hex_file(0x6000_0000, &[0xBB; 32])),
+            ],
+            Some(&device),
+        );
+
+        // As an upload would, rather than selecting the valid MODEL02 image
+        assert_eq!(
+            inspection.error,
+            Some(
+                VariantError::UnknownModel {
+                    model: "03".into(),
+                    name: "M8_V4_0_0_MODEL03.hex".into(),
+                }
+                .to_string()
+            )
+        );
+        assert!(inspection.entries.iter().all(|entry| !entry.selected));
+        assert!(inspection.entries[1].error.is_some());
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+};
+
+use sha1::{Digest, Sha1};
+
+/// What the GitHub contents API says an archive should look like once downloaded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExpectedArchive {
+    // Git blob SHA-1, which hashes a `blob <size>\0` header ahead of the contents
+    pub sha: String,
+    pub size: u64,
+}
+
+#[derive(Debug, thiserror::Error, Eq, PartialEq)]
+pub enum IntegrityError {
+    #[error("expected {expected} bytes but received {actual}")]
+    Size { actual: u64, expected: u64 },
+    #[error("checksum {actual} does not match the published {expected}")]
+    Sha { actual: String, expected: String },
+    #[error("unable to read the download: {0}")]
+    Read(String),
+}
+
+pub fn git_blob_sha1(reader: &mut impl Read, size: u64) -> io::Result<String> {
+    let mut hasher = Sha1::new();
+
+    hasher.update(format!("blob {}\0", size));
+
+    io::copy(reader, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn check(path: &Path, expected: &ExpectedArchive) -> Result<(), IntegrityError> {
+    let read_error = |error: io::Error| IntegrityError::Read(error.to_string());
+
+    let mut file = File::open(path).map_err(read_error)?;
+    let size = file.metadata().map_err(read_error)?.len();
+
+    if size != expected.size {
+        return Err(IntegrityError::Size {
+            actual: size,
+            expected: expected.size,
+        });
+    }
+
+    let sha = git_blob_sha1(&mut file, size).map_err(read_error)?;
+
+    if !sha.eq_ignore_ascii_case(&expected.sha) {
+        return Err(IntegrityError::Sha {
+            actual: sha,
+            expected: expected.sha.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks a downloaded archive, deleting it if it isn't what was published.
+pub fn verify_download(path: &Path, expected: &ExpectedArchive) -> Result<(), IntegrityError> {
+    check(path, expected).inspect_err(|error| {
+        log::error!("Discarding {:?}: {}", path, error);
+
+        if let Err(error) = fs::remove_file(path) {
+            log::warn!("Unable to delete {:?}: {}", path, error);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_git_hash_object() {
+        // `printf 'hello world\n' | git hash-object --stdin`
+        assert_eq!(
+            git_blob_sha1(&mut &b"hello world\n"[..], 12).unwrap(),
+            "3b18e512dba79e4c8300dd08aeb37f8e728b8dad"
+        );
+    }
+
+    #[test]
+    fn deletes_mismatched_downloads() {
+        let path = std::env::temp_dir().join(format!("m8-integrity-{}.zip", std::process::id()));
+
+        fs::write(&path, b"hello world\n").unwrap();
+
+        let expected = ExpectedArchive {
+            sha: "3B18E512DBA79E4C8300DD08AEB37F8E728B8DAD".into(),
+            size: 12,
+        };
+        assert_eq!(verify_download(&path, &expected), Ok(()));
+
+        let truncated = ExpectedArchive {
+            size: 20,
+            ..expected
+        };
+        assert!(matches!(
+            verify_download(&path, &truncated),
+            Err(IntegrityError::Size { .. })
+        ));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,532 @@
+use std::{fmt, ops::Range, path::Path};
+
+use serde::Serialize;
+
+use crate::firmware::{
+    board::{self, BoardEvidence},
+    version::M8Version,
+    DeviceType,
+};
+
+// The i.MX RT1062 maps its FlexSPI flash here on every Teensy 4.x board
+const FLEXSPI_BASE: u32 = 0x6000_0000;
+
+#[derive(Debug, thiserror::Error, Eq, PartialEq)]
+pub enum HexError {
+    #[error("Unable to read firmware image: {0}")]
+    Read(String),
+    #[error("Line {line}: missing ':' start code")]
+    StartCode { line: usize },
+    #[error("Line {line}: invalid hex digits")]
+    Digits { line: usize },
+    #[error("Line {line}: record length does not match its byte count")]
+    Length { line: usize },
+    #[error("Line {line}: checksum mismatch (expected {expected:#04x}, found {found:#04x})")]
+    Checksum {
+        expected: u8,
+        found: u8,
+        line: usize,
+    },
+    #[error("Line {line}: unsupported record type {record_type:#04x}")]
+    RecordType { line: usize, record_type: u8 },
+    #[error("Line {line}: malformed address record")]
+    AddressRecord { line: usize },
+    #[error("Line {line}: data after end-of-file record")]
+    DataAfterEof { line: usize },
+    #[error("Image is truncated (no end-of-file record)")]
+    MissingEof,
+    #[error("Image contains no data")]
+    Empty,
+    #[error("Image writes {address:#010x} more than once")]
+    Overlap { address: u32 },
+    #[error("No flash layout is known for device model {0:?}")]
+    UnknownBoard(DeviceType),
+    #[error(
+        "Image's boot configuration is for a {detected}, but the connected device is a {board}"
+    )]
+    BoardMismatch {
+        board: TeensyBoard,
+        detected: TeensyBoard,
+    },
+    #[error("Image spans {start:#010x}-{end:#010x}, outside the {board} flash ({flash_start:#010x}-{flash_end:#010x})")]
+    OutOfRange {
+        board: TeensyBoard,
+        end: u32,
+        flash_end: u32,
+        flash_start: u32,
+        start: u32,
+    },
+}
+
+/// Something about an image worth telling the user, but not enough to refuse it.
+#[derive(Debug, thiserror::Error, Eq, PartialEq)]
+pub enum HexWarning {
+    #[error(
+        "Image looks built for a {detected} ({evidence:?}), but the connected device is a {board}"
+    )]
+    BoardMismatch {
+        board: TeensyBoard,
+        detected: TeensyBoard,
+        evidence: BoardEvidence,
+    },
+    #[error("Image reports firmware {embedded}, but its file name says {expected}")]
+    VersionMismatch {
+        embedded: M8Version,
+        expected: M8Version,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum TeensyBoard {
+    MicroMod,
+    Teensy40,
+    Teensy41,
+}
+
+impl fmt::Display for TeensyBoard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TeensyBoard::MicroMod => "Teensy MicroMod",
+            TeensyBoard::Teensy40 => "Teensy 4.0",
+            TeensyBoard::Teensy41 => "Teensy 4.1",
+        })
+    }
+}
+
+impl TeensyBoard {
+    pub fn device_type(&self) -> DeviceType {
+        match self {
+            TeensyBoard::MicroMod => DeviceType::MODEL02,
+            TeensyBoard::Teensy40 => DeviceType::MODEL01,
+            TeensyBoard::Teensy41 => DeviceType::HEADLESS,
+        }
+    }
+
+    /// The size of the board's flash chip, which its boot configuration block records.
+    pub(crate) fn flash_chip_size(&self) -> u32 {
+        match self {
+            TeensyBoard::MicroMod => 0x0100_0000,
+            TeensyBoard::Teensy40 => 0x0020_0000,
+            TeensyBoard::Teensy41 => 0x0080_0000,
+        }
+    }
+
+    pub fn from_device_type(device_type: &DeviceType) -> Option<Self> {
+        match device_type {
+            DeviceType::HEADLESS => Some(TeensyBoard::Teensy41),
+            DeviceType::MODEL01 => Some(TeensyBoard::Teensy40),
+            DeviceType::MODEL02 => Some(TeensyBoard::MicroMod),
+            DeviceType::UNKNOWN => None,
+        }
+    }
+
+    /// The flash range a program may occupy. The top of each chip is reserved for
+    /// EEPROM emulation and the restore image, matching the Teensy Loader's limits.
+    pub fn program_flash(&self) -> Range<u32> {
+        let size: u32 = match self {
+            TeensyBoard::MicroMod => 16_515_072,
+            TeensyBoard::Teensy40 => 2_031_616,
+            TeensyBoard::Teensy41 => 8_126_464,
+        };
+
+        FLEXSPI_BASE..FLEXSPI_BASE + size
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HexSegment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl HexSegment {
+    pub fn end(&self) -> u32 {
+        self.address.saturating_add(self.data.len() as u32)
+    }
+}
+
+/// A decoded Intel HEX image: contiguous data segments in address order.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HexImage {
+    pub segments: Vec<HexSegment>,
+    pub start_address: Option<u32>,
+}
+
+impl HexImage {
+    pub fn address_range(&self) -> Option<Range<u32>> {
+        let start = self.segments.first()?.address;
+        let end = self.segments.last()?.end();
+
+        Some(start..end)
+    }
+
+    /// The `len` bytes starting at `address`, if a single segment holds all of them.
+    pub fn bytes_at(&self, address: u32, len: usize) -> Option<&[u8]> {
+        let segment = self
+            .segments
+            .iter()
+            .find(|segment| segment.address <= address && address < segment.end())?;
+
+        let start = (address - segment.address) as usize;
+
+        segment.data.get(start..start + len)
+    }
+
+    pub fn contains(&self, needle: &[u8]) -> bool {
+        if needle.is_empty() {
+            return false;
+        }
+
+        self.segments.iter().any(|segment| {
+            segment
+                .data
+                .windows(needle.len())
+                .any(|window| window == needle)
+        })
+    }
+
+    /// The version string the firmware embeds, if every segment agrees on one.
+    pub fn embedded_version(&self) -> Option<M8Version> {
+        let mut versions = self
+            .segments
+            .iter()
+            .filter_map(|segment| M8Version::find_embedded(&segment.data));
+
+        let first = versions.next()?;
+
+        versions.all(|version| version == first).then_some(first)
+    }
+
+    /// Number of data bytes in the image.
+    pub fn size(&self) -> usize {
+        self.segments.iter().map(|segment| segment.data.len()).sum()
+    }
+
+    /// Checks that the image fits the flash of `device_type` and wasn't built for another
+    /// board. Only a boot configuration block proves the board; weaker evidence, and an
+    /// embedded version other than the `expected` one, come back as warnings.
+    pub fn validate_for_device(
+        &self,
+        device_type: &DeviceType,
+        expected: Option<&M8Version>,
+    ) -> Result<Vec<HexWarning>, HexError> {
+        let board = TeensyBoard::from_device_type(device_type)
+            .ok_or_else(|| HexError::UnknownBoard(device_type.clone()))?;
+
+        self.validate_for(board)?;
+
+        let mut warnings = Vec::new();
+
+        let detection = board::detect(self);
+
+        if let (Some(detected), Some(evidence)) = (detection.board, detection.evidence) {
+            if detected != board {
+                if evidence == BoardEvidence::FlashConfig {
+                    return Err(HexError::BoardMismatch { board, detected });
+                }
+
+                warnings.push(HexWarning::BoardMismatch {
+                    board,
+                    detected,
+                    evidence,
+                });
+            }
+        }
+
+        if let (Some(embedded), Some(expected)) = (self.embedded_version(), expected) {
+            if &embedded != expected {
+                warnings.push(HexWarning::VersionMismatch {
+                    embedded,
+                    expected: expected.clone(),
+                });
+            }
+        }
+
+        Ok(warnings)
+    }
+
+    /// Checks that the whole image lands in `board`'s program flash.
+    pub fn validate_for(&self, board: TeensyBoard) -> Result<(), HexError> {
+        let range = self.address_range().ok_or(HexError::Empty)?;
+        let flash = board.program_flash();
+
+        if range.start < flash.start || range.end > flash.end {
+            return Err(HexError::OutOfRange {
+                board,
+                end: range.end,
+                flash_end: flash.end,
+                flash_start: flash.start,
+                start: range.start,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn decode_line(line: &str, number: usize) -> Result<Vec<u8>, HexError> {
+    let digits = line
+        .strip_prefix(':')
+        .ok_or(HexError::StartCode { line: number })?;
+
+    if digits.len() % 2 != 0 || !digits.is_ascii() {
+        return Err(HexError::Digits { line: number });
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| HexError::Digits { line: number })
+}
+
+pub fn parse(text: &str) -> Result<HexImage, HexError> {
+    let mut image = HexImage::default();
+    let mut segments: Vec<HexSegment> = Vec::new();
+    let mut upper_address: u32 = 0;
+    let mut eof_line: Option<usize> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if eof_line.is_some() {
+            return Err(HexError::DataAfterEof { line: number });
+        }
+
+        let bytes = decode_line(line, number)?;
+
+        // Byte count, 16-bit address, record type and checksum
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(HexError::Length { line: number });
+        }
+
+        let (body, checksum) = bytes.split_at(bytes.len() - 1);
+        let expected = body
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+            .wrapping_neg();
+
+        if expected != checksum[0] {
+            return Err(HexError::Checksum {
+                expected,
+                found: checksum[0],
+                line: number,
+            });
+        }
+
+        let offset = u16::from_be_bytes([body[1], body[2]]) as u32;
+        let record_type = body[3];
+        let data = &body[4..];
+
+        let address_field = || -> Result<u32, HexError> {
+            match data {
+                [high, low] => Ok(u16::from_be_bytes([*high, *low]) as u32),
+                _ => Err(HexError::AddressRecord { line: number }),
+            }
+        };
+
+        match record_type {
+            0x00 => {
+                let address = upper_address.wrapping_add(offset);
+
+                match segments.last_mut() {
+                    Some(segment) if segment.end() == address => {
+                        segment.data.extend_from_slice(data)
+                    }
+                    _ => segments.push(HexSegment {
+                        address,
+                        data: data.to_vec(),
+                    }),
+                }
+            }
+            0x01 => eof_line = Some(number),
+            0x02 => upper_address = address_field()? << 4,
+            0x04 => upper_address = address_field()? << 16,
+            0x03 | 0x05 => match data {
+                [a, b, c, d] => image.start_address = Some(u32::from_be_bytes([*a, *b, *c, *d])),
+                _ => return Err(HexError::AddressRecord { line: number }),
+            },
+            other => {
+                return Err(HexError::RecordType {
+                    line: number,
+                    record_type: other,
+                })
+            }
+        }
+    }
+
+    if eof_line.is_none() {
+        return Err(HexError::MissingEof);
+    }
+
+    segments.retain(|segment| !segment.data.is_empty());
+    segments.sort_by_key(|segment| segment.address);
+
+    for segment in segments {
+        match image.segments.last_mut() {
+            Some(previous) if previous.end() > segment.address => {
+                return Err(HexError::Overlap {
+                    address: segment.address,
+                })
+            }
+            Some(previous) if previous.end() == segment.address => {
+                previous.data.extend(segment.data)
+            }
+            _ => image.segments.push(segment),
+        }
+    }
+
+    if image.segments.is_empty() {
+        return Err(HexError::Empty);
+    }
+
+    Ok(image)
+}
+
+pub fn read(path: &Path) -> Result<HexImage, HexError> {
+    let text = std::fs::read_to_string(path).map_err(|error| HexError::Read(error.to_string()))?;
+
+    parse(&text)
+}
+
+/// Parses the image at `path` and checks that it fits the flash of `device_type` and,
+/// when the image embeds a version, that it is the `expected` one.
+pub fn validate_image(
+    path: &Path,
+    device_type: &DeviceType,
+    expected: Option<&M8Version>,
+) -> Result<(HexImage, Vec<HexWarning>), HexError> {
+    let image = read(path)?;
+
+    let warnings = image.validate_for_device(device_type, expected)?;
+
+    for warning in &warnings {
+        log::warn!("{:?}: {}", path, warning);
+    }
+
+    log::info!(
+        "Validated {:?} for {:?}: {} bytes at {:?}",
+        path,
+        device_type,
+        image.size(),
+        image.address_range()
+    );
+
+    Ok((image, warnings))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn record(offset: u16, record_type: u8, data: &[u8]) -> String {
+        let mut bytes = vec![data.len() as u8];
+        bytes.extend_from_slice(&offset.to_be_bytes());
+        bytes.push(record_type);
+        bytes.extend_from_slice(data);
+
+        let checksum = bytes
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+            .wrapping_neg();
+        bytes.push(checksum);
+
+        format!(
+            ":{}",
+            bytes
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<String>()
+        )
+    }
+
+    /// Builds a hex file placing `data` at `address`, 16 bytes per record.
+    pub(crate) fn hex_file(address: u32, data: &[u8]) -> String {
+        let mut lines = vec![record(0, 0x04, &((address >> 16) as u16).to_be_bytes())];
+
+        for (i, chunk) in data.chunks(16).enumerate() {
+            lines.push(record(
+                (address as u16).wrapping_add((i * 16) as u16),
+                0x00,
+                chunk,
+            ));
+        }
+
+        lines.push(record(0, 0x01, &[]));
+
+        lines.join("\n") + "\n"
+    }
+
+    #[test]
+    fn parses_segments_and_range() {
+        let image = parse(&hex_file(0x6000_0000, &[0xAA; 40])).unwrap();
+
+        assert_eq!(image.segments.len(), 1);
+        assert_eq!(image.size(), 40);
+        assert_eq!(image.address_range(), Some(0x6000_0000..0x6000_0028));
+        assert!(image.validate_for(TeensyBoard::Teensy40).is_ok());
+    }
+
+    #[test]
+    fn rejects_corrupt_records() {
+        let good = hex_file(0x6000_0000, &[1, 2, 3, 4]);
+
+        // Flip one data digit so the checksum no longer matches
+        let corrupt = good.replacen(":0400000001", ":0400000009", 1);
+        assert!(matches!(parse(&corrupt), Err(HexError::Checksum { .. })));
+
+        let truncated: String = good.lines().take(2).collect::<Vec<_>>().join("\n");
+        assert_eq!(parse(&truncated), Err(HexError::MissingEof));
+
+        let trailing = format!("{}{}\n", good, record(0, 0x00, &[1]));
+        assert!(matches!(
+            parse(&trailing),
+            Err(HexError::DataAfterEof { .. })
+        ));
+
+        let bad_address = format!("{}\n{}", record(0, 0x04, &[0x60]), record(0, 0x01, &[]));
+        assert!(matches!(
+            parse(&bad_address),
+            Err(HexError::AddressRecord { .. })
+        ));
+
+        assert!(matches!(
+            parse("0400000001020304F2\n"),
+            Err(HexError::StartCode { line: 1 })
+        ));
+    }
+
+    #[test]
+    fn rejects_images_outside_board_flash() {
+        // The top 64 KiB of a Teensy 4.0 is reserved, but a Teensy 4.1 has room to spare
+        let image = parse(&hex_file(0x601F_0000, &[0; 32])).unwrap();
+
+        assert!(matches!(
+            image.validate_for(TeensyBoard::Teensy40),
+            Err(HexError::OutOfRange { .. })
+        ));
+        assert!(image.validate_for(TeensyBoard::Teensy41).is_ok());
+
+        let low = parse(&hex_file(0x0000_1000, &[0; 32])).unwrap();
+        assert!(low.validate_for(TeensyBoard::MicroMod).is_err());
+    }
+
+    #[test]
+    fn warns_about_a_different_embedded_version() {
+        let image = parse(&hex_file(0x6000_0000, b"\x00M8 V4.0.1\x00")).unwrap();
+
+        let expected: M8Version = "4.0.2".parse().unwrap();
+        assert_eq!(
+            image.validate_for_device(&DeviceType::MODEL01, Some(&expected)),
+            Ok(vec![HexWarning::VersionMismatch {
+                embedded: "4.0.1".parse().unwrap(),
+                expected,
+            }])
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    fs,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use anyhow::Result;
+use tauri::{AppHandle, Manager};
+use tauri_plugin_fs::FilePath;
+use tauri_plugin_store::StoreExt;
+
+use crate::{
+    firmware::{
+        catalog::{self, FirmwareCatalog},
+        download::{DownloadError, DownloadManager},
+        download_verified, extract_into_cache,
+        integrity::ExpectedArchive,
+        is_hex,
+        sources::SETTINGS_STORE,
+        storage::{self, StorageError},
+    },
+    state::AppState,
+};
+
+const ENABLED_KEY: &str = "prefetchLatest";
+
+// Leaves startup to the catalog lookup the frontend makes anyway
+const STARTUP_DELAY: Duration = Duration::from_secs(60);
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+// Kept apart from `<version>.zip` so a flash never shares a file with a prefetch
+const PREFETCH_SUFFIX: &str = ".prefetch.zip";
+
+static PREFETCHING: AtomicBool = AtomicBool::new(false);
+
+/// Clears `PREFETCHING` however a prefetch ends, including by panicking.
+struct PrefetchGuard;
+
+impl Drop for PrefetchGuard {
+    fn drop(&mut self) {
+        PREFETCHING.store(false, Ordering::SeqCst);
+    }
+}
+
+pub fn load_enabled(app_handle: &AppHandle) -> bool {
+    app_handle
+        .store(SETTINGS_STORE)
+        .ok()
+        .and_then(|store| store.get(ENABLED_KEY))
+        .and_then(|value| value.as_bool())
+        .unwrap_or_default()
+}
+
+/// Checks the catalog every few hours while prefetching is enabled.
+pub fn start(app_handle: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(STARTUP_DELAY).await;
+
+        loop {
+            let enabled = app_handle.state::<AppState>().lock().await.prefetch_enabled;
+
+            if enabled {
+                if let Err(error) = prefetch_latest(&app_handle).await {
+                    log::warn!("Unable to prefetch the latest firmware: {}", error);
+                }
+            }
+
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+/// Downloads the newest release into the cache unless it is already there. Stands
+/// aside whenever a flash is in progress, cancelling its download if one starts.
+pub async fn prefetch_latest(app_handle: &AppHandle) -> Result<()> {
+    if PREFETCHING.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    let _guard = PrefetchGuard;
+
+    prefetch(app_handle).await
+}
+
+async fn prefetch(app_handle: &AppHandle) -> Result<()> {
+    let state = app_handle.state::<AppState>();
+
+    if state.lock().await.is_flashing() {
+        log::info!("Skipping prefetch while flashing");
+
+        return Ok(());
+    }
+
+    catalog::get_catalog(app_handle, false).await?;
+
+    let state_guard = state.lock().await;
+
+    let release = state_guard
+        .catalog
+        .as_ref()
+        .and_then(FirmwareCatalog::latest)
+        .cloned();
+
+    let temp_dir = state_guard.temp_dir.clone();
+
+    drop(state_guard);
+
+    let (Some(release), Some(temp_dir)) = (release, temp_dir) else {
+        return Ok(());
+    };
+
+    let Some(url) = release.download_url.clone() else {
+        return Ok(());
+    };
+
+    let mirrors = state.lock().await.sources.mirrors_for(&url);
+
+    let cached = {
+        let mut cache = storage::lock_cache(app_handle).await?;
+
+        mirrors
+            .iter()
+            .any(|(_, url)| cache.find_source(url, Some(&release.version)).is_some())
+    };
+
+    if cached {
+        log::info!("Firmware {} is already cached", release.version);
+
+        return Ok(());
+    }
+
+    log::info!("Prefetching firmware {}", release.version);
+
+    let destination = temp_dir
+        .as_path()
+        .ok_or_else(|| anyhow::Error::msg("Temp directory is not a local path"))?
+        .join(format!(
+            "{}{}",
+            release.version.file_stem(),
+            PREFETCH_SUFFIX
+        ));
+
+    let expected = release.sha.clone().map(|sha| ExpectedArchive {
+        sha,
+        size: release.size,
+    });
+
+    let shared_state = state.inner();
+    let downloads = app_handle.state::<DownloadManager>();
+    let downloads = downloads.inner();
+
+    let downloaded = download_verified(
+        app_handle,
+        &url,
+        destination.clone(),
+        expected.as_ref(),
+        |_, _| {
+            let destination = &destination;
+
+            async move {
+                if shared_state.lock().await.is_flashing() {
+                    log::info!("Flash started, cancelling prefetch");
+
+                    downloads.cancel(destination);
+                }
+            }
+        },
+    )
+    .await;
+
+    let path = match downloaded {
+        Ok(path) => path,
+        Err(error) if matches!(error.downcast_ref(), Some(DownloadError::Cancelled)) => {
+            return Ok(());
+        }
+        Err(error) => return Err(error),
+    };
+
+    // A flash starting from here on waits for the cache, so it can't overlap the extraction
+    let mut cache = storage::lock_cache(app_handle).await?;
+
+    let result = if state.lock().await.is_flashing() {
+        log::info!("Flash started, discarding prefetched archive");
+
+        Ok(())
+    } else {
+        extract_into_cache(
+            app_handle,
+            &mut cache,
+            FilePath::Path(path.clone()),
+            is_hex,
+            Some(url),
+            Some(release.version.clone()),
+        )
+        .await
+        .map(|_| log::info!("Prefetched firmware {}", release.version))
+    };
+
+    drop(cache);
+
+    if let Err(error) = fs::remove_file(&path) {
+        log::warn!("Unable to delete prefetched archive {:?}: {}", path, error);
+    }
+
+    result
+}
+
+#[tauri::command]
+pub async fn get_prefetch_enabled(app_handle: AppHandle) -> bool {
+    let state = app_handle.state::<AppState>();
+
+    let enabled = state.lock().await.prefetch_enabled;
+
+    enabled
+}
+
+#[tauri::command]
+pub async fn set_prefetch_enabled(
+    app_handle: AppHandle,
+    enabled: bool,
+) -> Result<(), StorageError> {
+    let store = app_handle
+        .store(SETTINGS_STORE)
+        .map_err(|error| StorageError::Store(error.to_string()))?;
+
+    store.set(ENABLED_KEY, enabled);
+    store
+        .save()
+        .map_err(|error| StorageError::Store(error.to_string()))?;
+
+    app_handle.state::<AppState>().lock().await.prefetch_enabled = enabled;
+
+    if enabled {
+        tauri::async_runtime::spawn(async move {
+            if let Err(error) = prefetch_latest(&app_handle).await {
+                log::warn!("Unable to prefetch the latest firmware: {}", error);
+            }
+        });
+    }
+
+    Ok(())
+}
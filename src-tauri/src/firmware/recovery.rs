@@ -0,0 +1,216 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tauri_plugin_store::StoreExt;
+
+use crate::{
+    firmware::{
+        download::PARTIAL_SUFFIX, sources::SETTINGS_STORE, storage::StorageError,
+        version::M8Version,
+    },
+    state::AppState,
+};
+
+const JOB_KEY: &str = "inFlightFlash";
+
+// Partial downloads younger than this are kept so the next download resumes them
+const STALE_PARTIAL_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum FlashStage {
+    Downloading,
+    // The device may have been left in its bootloader
+    Uploading,
+}
+
+/// A flash that has started, persisted until it ends so a crash can be noticed.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FlashJob {
+    pub device_serial: Option<String>,
+    // Set for local files; remote versions are found through the catalog again
+    pub path: Option<String>,
+    pub stage: FlashStage,
+    pub started_at: i64,
+    pub version: Option<M8Version>,
+}
+
+fn save_job(app_handle: &AppHandle, job: Option<&FlashJob>) -> Result<(), StorageError> {
+    let store = app_handle
+        .store(SETTINGS_STORE)
+        .map_err(|error| StorageError::Store(error.to_string()))?;
+
+    match job {
+        Some(job) => store.set(
+            JOB_KEY,
+            serde_json::to_value(job).map_err(|error| StorageError::Store(error.to_string()))?,
+        ),
+        None => {
+            store.delete(JOB_KEY);
+        }
+    }
+
+    store
+        .save()
+        .map_err(|error| StorageError::Store(error.to_string()))
+}
+
+fn load_job(app_handle: &AppHandle) -> Option<FlashJob> {
+    app_handle
+        .store(SETTINGS_STORE)
+        .ok()
+        .and_then(|store| store.get(JOB_KEY))
+        .and_then(|value| {
+            serde_json::from_value(value)
+                .inspect_err(|error| log::warn!("Ignoring invalid flash marker: {}", error))
+                .ok()
+        })
+}
+
+/// Records that a flash has started, replacing any interrupted one.
+pub async fn begin(app_handle: &AppHandle, job: FlashJob) {
+    if let Err(error) = save_job(app_handle, Some(&job)) {
+        log::warn!("Unable to record flash: {}", error);
+    }
+
+    app_handle
+        .state::<AppState>()
+        .lock()
+        .await
+        .interrupted_flash = None;
+}
+
+/// Moves the recorded flash on to `stage`.
+pub fn advance(app_handle: &AppHandle, stage: FlashStage) {
+    let Some(mut job) = load_job(app_handle) else {
+        return;
+    };
+
+    job.stage = stage;
+
+    if let Err(error) = save_job(app_handle, Some(&job)) {
+        log::warn!("Unable to record flash: {}", error);
+    }
+}
+
+/// Records that the flash ended, however it ended, while the app was there to see it.
+pub fn finish(app_handle: &AppHandle) {
+    if let Err(error) = save_job(app_handle, None) {
+        log::warn!("Unable to clear flash marker: {}", error);
+    }
+}
+
+/// Deletes what earlier runs left in the download directory: archives that were never
+/// extracted, and partial downloads too old to be worth resuming.
+fn clean_downloads(dir: &Path, now: SystemTime) -> io::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let is_partial = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(PARTIAL_SUFFIX));
+
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+
+        if is_partial && age < STALE_PARTIAL_AGE {
+            log::info!("Keeping {:?} to resume", path);
+
+            continue;
+        }
+
+        fs::remove_file(&path)?;
+
+        removed.push(path);
+    }
+
+    Ok(removed)
+}
+
+/// Tidies up after a previous run that ended mid-flash, and remembers whether it did.
+pub fn recover(app_handle: &AppHandle) {
+    let state = app_handle.state::<AppState>();
+
+    let temp_dir = state.blocking_lock().temp_dir.clone();
+
+    if let Some(dir) = temp_dir.as_ref().and_then(|dir| dir.as_path()) {
+        match clean_downloads(dir, SystemTime::now()) {
+            Ok(removed) if !removed.is_empty() => {
+                log::info!("Removed {} leftover download(s)", removed.len())
+            }
+            Ok(_) => {}
+            Err(error) => log::warn!("Unable to clean up downloads: {}", error),
+        }
+    }
+
+    let job = load_job(app_handle);
+
+    if let Some(job) = &job {
+        log::warn!("A previous flash did not complete: {:?}", job);
+    }
+
+    state.blocking_lock().interrupted_flash = job;
+}
+
+#[tauri::command]
+pub async fn get_interrupted_flash(app_handle: AppHandle) -> Option<FlashJob> {
+    let state = app_handle.state::<AppState>();
+
+    let job = state.lock().await.interrupted_flash.clone();
+
+    job
+}
+
+#[tauri::command]
+pub async fn dismiss_interrupted_flash(app_handle: AppHandle) -> Result<(), StorageError> {
+    let state = app_handle.state::<AppState>();
+
+    // Starting another flash clears this, so the marker is still the interrupted one
+    if state.lock().await.interrupted_flash.take().is_some() {
+        save_job(&app_handle, None)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_leftovers_and_keeps_recent_partials() {
+        let dir = std::env::temp_dir().join(format!("m8-recovery-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let archive = dir.join("4_0_1.zip");
+        let partial = dir.join("4_0_2.zip.part");
+        fs::write(&archive, b"complete").unwrap();
+        fs::write(&partial, b"half").unwrap();
+
+        let removed = clean_downloads(&dir, SystemTime::now()).unwrap();
+        assert_eq!(removed, vec![archive]);
+        assert!(partial.exists());
+
+        // A day later, the partial is no longer worth resuming
+        let later = SystemTime::now() + STALE_PARTIAL_AGE + Duration::from_secs(1);
+        assert_eq!(clean_downloads(&dir, later).unwrap(), vec![partial]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::{
+    firmware::{
+        catalog::{self, CatalogError, FirmwareRelease},
+        integrity::ExpectedArchive,
+        start_firmware_download_handler,
+        version::M8Version,
+        ArchiveSource, ConnectedDevice, DeviceType,
+    },
+    serial::device::DeviceError,
+    state::{AppState, AppStateData},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RestoreError {
+    #[error("A flash is already in progress")]
+    Busy,
+    #[error("Restoring {0} has to be confirmed")]
+    NotConfirmed(String),
+    #[error("{0} is running its firmware and doesn't need restoring")]
+    NotNeeded(String),
+    #[error("No stable firmware release is available to restore")]
+    NoRelease,
+    #[error(transparent)]
+    Catalog(#[from] CatalogError),
+    #[error(transparent)]
+    Device(#[from] DeviceError),
+}
+
+impl Serialize for RestoreError {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_str())
+    }
+}
+
+/// The newest release that isn't a beta, which is what a board is restored to.
+fn known_good(releases: &[FirmwareRelease]) -> Option<&FirmwareRelease> {
+    releases
+        .iter()
+        .filter(|release| release.download_url.is_some() && release.version.beta.is_none())
+        .max_by(|a, b| a.version.cmp(&b.version))
+}
+
+/// What restoring a board will do, shown to the user before they agree to it.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct RestorePlan {
+    pub device_type: DeviceType,
+    pub model: String,
+    pub tag: String,
+    // Handed back to `restore_device` once the user agrees
+    pub token: u64,
+    pub version: M8Version,
+}
+
+/// A plan the user has been shown but not yet answered.
+#[derive(Clone, Debug)]
+pub struct PendingRestore {
+    pub release: FirmwareRelease,
+    pub tag: String,
+    pub token: u64,
+}
+
+/// The firmware a restore selected in place of the user's choice.
+#[derive(Clone, Debug, Serialize)]
+struct RestoreSelection {
+    path: String,
+    version: M8Version,
+}
+
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(1);
+
+/// The board behind `tag`, if it is connected and has no working firmware.
+fn recoverable(state: &AppStateData, tag: &str) -> Result<ConnectedDevice, RestoreError> {
+    if state.is_flashing() {
+        return Err(RestoreError::Busy);
+    }
+
+    let device = state
+        .devices
+        .get(tag)
+        .cloned()
+        .ok_or_else(|| DeviceError::Unknown(tag.to_owned()))?;
+
+    if !device.needs_recovery() {
+        return Err(RestoreError::NotNeeded(tag.to_owned()));
+    }
+
+    Ok(device)
+}
+
+/// The pending restore of `tag`, if `token` is the one its plan was shown with. A plan
+/// is only good for one answer.
+fn take_pending(state: &mut AppStateData, tag: &str, token: u64) -> Option<PendingRestore> {
+    state
+        .pending_restore
+        .take()
+        .filter(|pending| pending.tag == tag && pending.token == token)
+}
+
+/// First step of restoring a board stuck in its bootloader or left blank: picks the
+/// newest stable firmware for the model the bootloader reports, for the user to confirm.
+/// The board can't confirm it is an M8, so nothing is flashed until `restore_device` is
+/// called with the plan's token.
+#[tauri::command]
+pub async fn plan_restore(app_handle: AppHandle, tag: String) -> Result<RestorePlan, RestoreError> {
+    let state = app_handle.state::<AppState>();
+
+    let device = recoverable(&*state.lock().await, &tag)?;
+
+    let releases = catalog::get_catalog(&app_handle, false).await?;
+
+    let release = known_good(&releases).ok_or(RestoreError::NoRelease)?;
+
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+
+    state.lock().await.pending_restore = Some(PendingRestore {
+        release: release.clone(),
+        tag: tag.clone(),
+        token,
+    });
+
+    Ok(RestorePlan {
+        device_type: device.device_type,
+        model: device.ty_cmd_info.model,
+        tag,
+        token,
+        version: release.version.clone(),
+    })
+}
+
+/// Second step: flashes the firmware of the plan the user agreed to. The selected
+/// firmware is replaced by it, which the frontend hears about as `restore-selected`.
+#[tauri::command]
+pub async fn restore_device(
+    app_handle: AppHandle,
+    tag: String,
+    token: u64,
+) -> Result<M8Version, RestoreError> {
+    let state = app_handle.state::<AppState>();
+
+    let mut state_guard = state.lock().await;
+
+    let device = recoverable(&state_guard, &tag)?;
+
+    let PendingRestore { release, .. } = take_pending(&mut state_guard, &tag, token)
+        .ok_or_else(|| RestoreError::NotConfirmed(tag.clone()))?;
+
+    log::warn!(
+        "Restoring {} ({:?}, {:?}) to firmware {}",
+        tag,
+        device.device_type,
+        device.mode,
+        release.version
+    );
+
+    state_guard.select_device(&tag)?;
+
+    state_guard.archive_source =
+        ArchiveSource::RemoteUrl(release.download_url.clone().unwrap_or_default());
+    state_guard.confirmed_restore = Some(tag);
+    state_guard.expected_archive = release.sha.clone().map(|sha| ExpectedArchive {
+        sha,
+        size: release.size,
+    });
+    state_guard.size = release.size;
+    state_guard.version = Some(release.version.clone());
+
+    let _ = state_guard.emit_device_state_update(&app_handle);
+
+    drop(state_guard);
+
+    let _ = app_handle.emit_to(
+        "main",
+        "restore-selected",
+        RestoreSelection {
+            path: release.path.clone(),
+            version: release.version.clone(),
+        },
+    );
+
+    start_firmware_download_handler(Arc::new(app_handle.clone()));
+
+    Ok(release.version)
+}
+
+/// Why a flash to `device_type` in recovery can't go ahead without a confirmed restore.
+pub fn unconfirmed_message(tag: &str, device_type: &DeviceType) -> String {
+    format!(
+        "{} ({:?}) has no working firmware; restore it and confirm first",
+        tag, device_type
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(version: &str, download_url: Option<&str>) -> FirmwareRelease {
+        FirmwareRelease {
+            changelog: vec![],
+            date: None,
+            download_url: download_url.map(Into::into),
+            path: String::new(),
+            sha: None,
+            size: 0,
+            version: version.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn restores_the_newest_stable_release() {
+        let releases = [
+            release("4.0.0", Some("https://example.com/4_0_0.zip")),
+            release("4.1.0", None),
+            release("4.0.1", Some("https://example.com/4_0_1.zip")),
+            release("4.1.0 Beta 2", Some("https://example.com/4_1_0_beta2.zip")),
+        ];
+
+        assert_eq!(
+            known_good(&releases).map(|release| release.version.to_string()),
+            Some("4.0.1".to_string())
+        );
+        assert!(known_good(&releases[1..2]).is_none());
+    }
+
+    #[test]
+    fn a_plan_is_good_for_one_matching_answer() {
+        let pending = |token| {
+            Some(PendingRestore {
+                release: release("4.0.1", Some("https://example.com/4_0_1.zip")),
+                tag: "123-Teensy".into(),
+                token,
+            })
+        };
+
+        let mut state = AppStateData::default();
+
+        state.pending_restore = pending(7);
+        assert!(take_pending(&mut state, "123-Teensy", 8).is_none());
+        // A wrong answer spends the plan too
+        assert!(state.pending_restore.is_none());
+
+        state.pending_restore = pending(9);
+        assert!(take_pending(&mut state, "456-Teensy", 9).is_none());
+
+        state.pending_restore = pending(10);
+        let taken = take_pending(&mut state, "123-Teensy", 10).unwrap();
+        assert_eq!(taken.release.version.to_string(), "4.0.1");
+        assert!(take_pending(&mut state, "123-Teensy", 10).is_none());
+    }
+}
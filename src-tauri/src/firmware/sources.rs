@@ -0,0 +1,327 @@
+use std::{collections::BTreeMap, future::Future, time::Duration};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tauri_plugin_store::StoreExt;
+
+use crate::{firmware::catalog::CatalogError, state::AppState};
+
+pub const SETTINGS_STORE: &str = "settings.json";
+
+const SOURCES_KEY: &str = "firmwareSources";
+
+const GITHUB_CONTENTS_URL: &str = "https://api.github.com/repos/Dirtywave/M8Firmware/contents";
+
+const GITHUB_API_HOST: &str = "api.github.com";
+
+const USER_AGENT_VALUE: &str = "com.dirtywave.updater";
+
+const ATTEMPTS_PER_SOURCE: u32 = 3;
+
+// Doubled after every failed attempt against the same source
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// A tree laid out like the GitHub contents API of Dirtywave/M8Firmware, with
+/// `changelog.txt`, a `Releases` listing and the latest `M8Firmware.zip`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FirmwareSource {
+    pub base_url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub name: String,
+}
+
+impl FirmwareSource {
+    pub fn github() -> Self {
+        Self {
+            base_url: GITHUB_CONTENTS_URL.to_owned(),
+            headers: BTreeMap::from([("X-GitHub-Api-Version".to_owned(), "2022-11-28".to_owned())]),
+            name: "GitHub".to_owned(),
+        }
+    }
+
+    fn base(&self) -> &str {
+        self.base_url.trim_end_matches('/')
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base(), path.trim_start_matches('/'))
+    }
+
+    /// The path of `url` below this source, if it points into it.
+    pub fn relative_path<'a>(&self, url: &'a str) -> Option<&'a str> {
+        url.strip_prefix(self.base())?.strip_prefix('/')
+    }
+
+    fn is_github(&self) -> bool {
+        reqwest::Url::parse(&self.base_url).is_ok_and(|url| url.host_str() == Some(GITHUB_API_HOST))
+    }
+
+    /// Request headers for this source. `accept` selects the GitHub media type, which
+    /// mirrors are free to ignore. The user's GitHub `token` is only sent to GitHub.
+    pub fn headers(&self, accept: &str, token: Option<&str>) -> Result<HeaderMap, CatalogError> {
+        let mut headers = HeaderMap::new();
+
+        headers.insert(
+            ACCEPT,
+            format!("application/vnd.github.{}+json", accept).parse()?,
+        );
+        headers.insert(USER_AGENT, USER_AGENT_VALUE.parse()?);
+
+        if let Some(token) = token.filter(|_| self.is_github()) {
+            headers.insert(AUTHORIZATION, format!("Bearer {}", token).parse()?);
+        }
+
+        for (name, value) in &self.headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| CatalogError::InvalidSource(format!("header name {}", name)))?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+
+        Ok(headers)
+    }
+}
+
+/// Where firmware is fetched from, in the order sources are tried.
+///
+/// A mirror serves the files of the GitHub contents API as-is: `changelog.txt` as plain
+/// text, `Releases` as a JSON array of entries and `M8Firmware.zip` as a single entry,
+/// each entry carrying the archive's `name`, `path`, git blob `sha` and `size`:
+///
+/// ```json
+/// [{ "name": "M8_V4_0_1.zip", "path": "Releases/M8_V4_0_1.zip", "sha": "3b18e5…", "size": 1048576 }]
+/// ```
+///
+/// Archives are then downloaded from `<base_url>/<path>`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SourceConfig {
+    pub sources: Vec<FirmwareSource>,
+}
+
+impl Default for SourceConfig {
+    fn default() -> Self {
+        Self {
+            sources: vec![FirmwareSource::github()],
+        }
+    }
+}
+
+impl SourceConfig {
+    pub fn validate(&self) -> Result<(), CatalogError> {
+        if self.sources.is_empty() {
+            return Err(CatalogError::NoSources);
+        }
+
+        for source in &self.sources {
+            reqwest::Url::parse(&source.base_url).map_err(|error| {
+                CatalogError::InvalidSource(format!("{} ({})", source.base_url, error))
+            })?;
+
+            source.headers("raw", None)?;
+        }
+
+        Ok(())
+    }
+
+    /// Every source's copy of the resource at `url`, in failover order. A URL that
+    /// doesn't belong to any configured source is only tried as-is.
+    pub fn mirrors_for(&self, url: &str) -> Vec<(FirmwareSource, String)> {
+        let Some(path) = self
+            .sources
+            .iter()
+            .find_map(|source| source.relative_path(url))
+        else {
+            let source = FirmwareSource {
+                base_url: url.to_owned(),
+                headers: BTreeMap::new(),
+                name: url.to_owned(),
+            };
+
+            return vec![(source, url.to_owned())];
+        };
+
+        self.sources
+            .iter()
+            .map(|source| (source.clone(), source.url(path)))
+            .collect()
+    }
+}
+
+fn is_retryable(error: &CatalogError) -> bool {
+    match error {
+        CatalogError::Request(_) => true,
+        CatalogError::Status { status, .. } => *status == 429 || *status >= 500,
+        _ => false,
+    }
+}
+
+/// Runs `operation` against each source in turn, retrying transient failures with
+/// backoff before moving on to the next source. Returns the source that succeeded.
+pub async fn with_failover<T, F, Fut>(
+    sources: &[FirmwareSource],
+    mut operation: F,
+) -> Result<(T, FirmwareSource), CatalogError>
+where
+    F: FnMut(FirmwareSource) -> Fut,
+    Fut: Future<Output = Result<T, CatalogError>>,
+{
+    let mut last_error = None;
+
+    for source in sources {
+        let mut backoff = INITIAL_BACKOFF;
+
+        for attempt in 1..=ATTEMPTS_PER_SOURCE {
+            match operation(source.clone()).await {
+                Ok(value) => return Ok((value, source.clone())),
+                Err(error) => {
+                    log::warn!("{} attempt {} failed: {}", source.name, attempt, error);
+
+                    let retry = is_retryable(&error) && attempt < ATTEMPTS_PER_SOURCE;
+
+                    last_error = Some(error);
+
+                    if !retry {
+                        break;
+                    }
+
+                    tokio::time::sleep(backoff).await;
+
+                    backoff *= 2;
+                }
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or(CatalogError::NoSources))
+}
+
+/// Reads the source configuration from the settings store, falling back to GitHub.
+pub fn load_sources(app_handle: &AppHandle) -> SourceConfig {
+    let stored = app_handle
+        .store(SETTINGS_STORE)
+        .ok()
+        .and_then(|store| store.get(SOURCES_KEY));
+
+    match stored.map(serde_json::from_value::<SourceConfig>) {
+        Some(Ok(config)) if config.validate().is_ok() => config,
+        Some(_) => {
+            log::warn!("Ignoring invalid firmware source configuration");
+
+            SourceConfig::default()
+        }
+        None => SourceConfig::default(),
+    }
+}
+
+#[tauri::command]
+pub async fn get_firmware_sources(app_handle: AppHandle) -> Result<SourceConfig, CatalogError> {
+    let state = app_handle.state::<AppState>();
+
+    let sources = state.lock().await.sources.clone();
+
+    Ok(sources)
+}
+
+#[tauri::command]
+pub async fn set_firmware_sources(
+    app_handle: AppHandle,
+    config: SourceConfig,
+) -> Result<(), CatalogError> {
+    config.validate()?;
+
+    let store = app_handle
+        .store(SETTINGS_STORE)
+        .map_err(|error| CatalogError::Store(error.to_string()))?;
+
+    store.set(SOURCES_KEY, serde_json::to_value(&config)?);
+    store
+        .save()
+        .map_err(|error| CatalogError::Store(error.to_string()))?;
+
+    let state = app_handle.state::<AppState>();
+    let mut state_guard = state.lock().await;
+
+    state_guard.sources = config;
+
+    // The next lookup fetches the catalog from the new sources
+    state_guard.catalog = None;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirror() -> FirmwareSource {
+        FirmwareSource {
+            base_url: "http://mirror.local/m8/".into(),
+            headers: BTreeMap::from([("Authorization".into(), "Bearer abc".into())]),
+            name: "Studio mirror".into(),
+        }
+    }
+
+    #[test]
+    fn maps_urls_across_mirrors() {
+        let config = SourceConfig {
+            sources: vec![mirror(), FirmwareSource::github()],
+        };
+
+        let mirrors = config.mirrors_for(&FirmwareSource::github().url("Releases/M8_V4.zip"));
+
+        assert_eq!(
+            mirrors
+                .iter()
+                .map(|(_, url)| url.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "http://mirror.local/m8/Releases/M8_V4.zip",
+                "https://api.github.com/repos/Dirtywave/M8Firmware/contents/Releases/M8_V4.zip",
+            ]
+        );
+
+        assert_eq!(config.mirrors_for("http://elsewhere/a.zip").len(), 1);
+        assert_eq!(
+            mirror().headers("raw", Some("token")).unwrap()["authorization"],
+            "Bearer abc"
+        );
+        assert_eq!(
+            FirmwareSource::github()
+                .headers("raw", Some("token"))
+                .unwrap()["authorization"],
+            "Bearer token"
+        );
+    }
+
+    #[test]
+    fn fails_over_to_the_next_source() {
+        let sources = [mirror(), FirmwareSource::github()];
+        let mut tried: Vec<String> = Vec::new();
+
+        let result = tauri::async_runtime::block_on(with_failover(&sources, |source| {
+            tried.push(source.name.clone());
+
+            async move {
+                if source.name == "GitHub" {
+                    Ok(1)
+                } else {
+                    Err(CatalogError::Status {
+                        body: String::new(),
+                        status: 404,
+                    })
+                }
+            }
+        }));
+
+        let (value, source) = result.unwrap();
+        assert_eq!(value, 1);
+        assert_eq!(source.name, "GitHub");
+        // A 404 isn't transient, so the mirror is only asked once
+        assert_eq!(tried, vec!["Studio mirror", "GitHub"]);
+
+        assert!(SourceConfig { sources: vec![] }.validate().is_err());
+    }
+}
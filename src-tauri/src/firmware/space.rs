@@ -0,0 +1,162 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::firmware::archive::ExtractionLimits;
+
+// Left free after everything is written, so the rest of the system keeps working
+const HEADROOM_BYTES: u64 = 16 * 1024 * 1024;
+
+// Hex images are text and compress to about a third; allow for tighter formats
+const EXTRACTION_FACTOR: u64 = 4;
+
+const MIB: u64 = 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SpaceError {
+    #[error(
+        "Not enough disk space in {path}: {} MiB needed, {} MiB available",
+        .needed.div_ceil(MIB),
+        .available / MIB
+    )]
+    Insufficient {
+        available: u64,
+        needed: u64,
+        path: String,
+    },
+    #[error("Unable to check free space in {path}: {error}")]
+    Io { error: io::Error, path: String },
+}
+
+/// Bytes to be written under a directory.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SpaceNeeded {
+    pub bytes: u64,
+    pub dir: PathBuf,
+}
+
+/// How much room the images of an archive of `archive_size` bytes may take once
+/// extracted, never more than extraction allows.
+pub fn extracted_estimate(archive_size: u64) -> u64 {
+    archive_size
+        .saturating_mul(EXTRACTION_FACTOR)
+        .min(ExtractionLimits::default().max_total_bytes)
+}
+
+/// The closest directory to `path` that exists, since the cache may not have been
+/// created yet.
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors().find(|dir| dir.exists()).unwrap_or(path)
+}
+
+#[cfg(unix)]
+fn volume(path: &Path) -> io::Result<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(path.metadata()?.dev().to_string())
+}
+
+#[cfg(not(unix))]
+fn volume(path: &Path) -> io::Result<String> {
+    // The drive or share the path is on
+    let path = path.canonicalize()?;
+
+    Ok(path
+        .components()
+        .next()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .unwrap_or_default())
+}
+
+/// Adds up what each filesystem must hold, so a temp and cache directory on the same
+/// disk are checked together.
+fn group_by_volume(
+    needs: &[SpaceNeeded],
+    volume: impl Fn(&Path) -> io::Result<String>,
+) -> Result<BTreeMap<String, (PathBuf, u64)>, SpaceError> {
+    let mut volumes: BTreeMap<String, (PathBuf, u64)> = BTreeMap::new();
+
+    for need in needs {
+        let dir = existing_ancestor(&need.dir);
+
+        let key = volume(dir).map_err(|error| SpaceError::Io {
+            error,
+            path: dir.display().to_string(),
+        })?;
+
+        volumes.entry(key).or_insert((dir.to_owned(), 0)).1 += need.bytes;
+    }
+
+    Ok(volumes)
+}
+
+/// Fails unless every filesystem involved has room for what will be written to it.
+pub fn preflight(needs: &[SpaceNeeded]) -> Result<(), SpaceError> {
+    for (dir, bytes) in group_by_volume(needs, volume)?.into_values() {
+        let available = fs4::available_space(&dir).map_err(|error| SpaceError::Io {
+            error,
+            path: dir.display().to_string(),
+        })?;
+
+        let needed = bytes.saturating_add(HEADROOM_BYTES);
+
+        log::info!(
+            "{} bytes needed in {:?}, {} available",
+            needed,
+            dir,
+            available
+        );
+
+        if available < needed {
+            return Err(SpaceError::Insufficient {
+                available,
+                needed,
+                path: dir.display().to_string(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_filesystems_are_checked_together() {
+        let temp = std::env::temp_dir();
+
+        let needs = [
+            SpaceNeeded {
+                bytes: 10,
+                dir: temp.join("m8-space-missing/firmware"),
+            },
+            SpaceNeeded {
+                bytes: 30,
+                dir: temp.clone(),
+            },
+        ];
+
+        let volumes = group_by_volume(&needs, |_| Ok("disk".into())).unwrap();
+        assert_eq!(volumes["disk"], (temp.clone(), 40));
+
+        let volumes = group_by_volume(&needs, |path| Ok(path.display().to_string())).unwrap();
+        assert_eq!(volumes.len(), 1);
+
+        let error = preflight(&[SpaceNeeded {
+            bytes: u64::MAX,
+            dir: temp,
+        }])
+        .unwrap_err();
+        assert!(error.to_string().starts_with("Not enough disk space"));
+
+        assert_eq!(extracted_estimate(1024), 4096);
+        assert_eq!(
+            extracted_estimate(u64::MAX),
+            ExtractionLimits::default().max_total_bytes
+        );
+    }
+}
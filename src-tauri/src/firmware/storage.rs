@@ -0,0 +1,254 @@
+use std::path::PathBuf;
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+use tauri_plugin_store::StoreExt;
+use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard};
+
+use crate::{
+    firmware::{
+        cache::{CacheEntry, CacheError, EvictionPolicy, FirmwareCache, CACHE_DIR_NAME},
+        sources::SETTINGS_STORE,
+        version::{M8Version, VersionParseError},
+    },
+    state::AppState,
+};
+
+const POLICY_KEY: &str = "cachePolicy";
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error(transparent)]
+    Cache(#[from] CacheError),
+    #[error("Firmware cache is unavailable: {0}")]
+    Unavailable(&'static str),
+    #[error("Unable to save settings: {0}")]
+    Store(String),
+    #[error(transparent)]
+    Version(#[from] VersionParseError),
+    #[error("Version {0} is not cached")]
+    NotCached(String),
+}
+
+impl Serialize for StorageError {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_str())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct CachedFirmware {
+    pub archive_sha256: String,
+    pub cached_at: i64,
+    pub last_used_at: i64,
+    pub pinned: bool,
+    pub size: u64,
+    pub source_url: Option<String>,
+    pub version: Option<M8Version>,
+}
+
+impl From<&CacheEntry> for CachedFirmware {
+    fn from(entry: &CacheEntry) -> Self {
+        Self {
+            archive_sha256: entry.archive_sha256.clone(),
+            cached_at: entry.cached_at,
+            last_used_at: entry.last_used_at.max(entry.cached_at),
+            pinned: entry.pinned,
+            size: entry.size(),
+            source_url: entry.source_url.clone(),
+            version: entry.version.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct StorageUsage {
+    // Newest version first
+    pub entries: Vec<CachedFirmware>,
+    pub policy: EvictionPolicy,
+    pub total_bytes: u64,
+}
+
+fn usage(cache: &FirmwareCache, policy: EvictionPolicy) -> StorageUsage {
+    let mut entries: Vec<CachedFirmware> = cache.entries().iter().map(Into::into).collect();
+
+    entries.sort_by(|a, b| b.version.cmp(&a.version));
+
+    StorageUsage {
+        entries,
+        policy,
+        total_bytes: cache.total_size(),
+    }
+}
+
+/// Where the firmware cache lives, whether or not it has been created yet.
+pub async fn cache_root(app_handle: &AppHandle) -> Result<PathBuf, StorageError> {
+    let state = app_handle.state::<AppState>();
+
+    let cache_dir = state
+        .lock()
+        .await
+        .cache_dir
+        .clone()
+        .ok_or(StorageError::Unavailable("cache directory not set"))?;
+
+    let root = cache_dir
+        .as_path()
+        .ok_or(StorageError::Unavailable(
+            "cache directory is not a local path",
+        ))?
+        .join(CACHE_DIR_NAME);
+
+    Ok(root)
+}
+
+/// The firmware cache every caller goes through, opened on first use. Sharing one copy
+/// of the index keeps callers from overwriting each other's changes with a stale one.
+#[derive(Default)]
+pub struct SharedCache(Mutex<Option<FirmwareCache>>);
+
+/// Locks the shared cache, opening it if needed. The guard must be dropped before the
+/// cache is locked again, including through the helpers below.
+pub async fn lock_cache(
+    app_handle: &AppHandle,
+) -> Result<MappedMutexGuard<'_, FirmwareCache>, StorageError> {
+    let root = cache_root(app_handle).await?;
+
+    let mut guard = app_handle.state::<SharedCache>().inner().0.lock().await;
+
+    let cache = match guard.take() {
+        Some(cache) => cache,
+        None => FirmwareCache::open(root)?,
+    };
+
+    Ok(MutexGuard::map(guard, |slot| slot.insert(cache)))
+}
+
+/// Reads the eviction policy from the settings store, falling back to the default.
+pub fn load_policy(app_handle: &AppHandle) -> EvictionPolicy {
+    app_handle
+        .store(SETTINGS_STORE)
+        .ok()
+        .and_then(|store| store.get(POLICY_KEY))
+        .and_then(|value| {
+            serde_json::from_value(value)
+                .inspect_err(|error| log::warn!("Ignoring invalid cache policy: {}", error))
+                .ok()
+        })
+        .unwrap_or_default()
+}
+
+/// Trims the cache back to the configured policy.
+pub async fn enforce_policy(app_handle: &AppHandle) -> Result<Vec<CacheEntry>, StorageError> {
+    let mut cache = lock_cache(app_handle).await?;
+
+    evict_by_policy(app_handle, &mut cache).await
+}
+
+/// Like `enforce_policy`, for callers already holding the cache lock.
+pub async fn evict_by_policy(
+    app_handle: &AppHandle,
+    cache: &mut FirmwareCache,
+) -> Result<Vec<CacheEntry>, StorageError> {
+    let policy = app_handle
+        .state::<AppState>()
+        .lock()
+        .await
+        .cache_policy
+        .clone();
+
+    let evicted = cache.evict(&policy, chrono::Utc::now().timestamp_millis())?;
+
+    if !evicted.is_empty() {
+        log::info!("Evicted {} cached firmware archive(s)", evicted.len());
+    }
+
+    Ok(evicted)
+}
+
+async fn current_usage(app_handle: &AppHandle) -> Result<StorageUsage, StorageError> {
+    let policy = app_handle
+        .state::<AppState>()
+        .lock()
+        .await
+        .cache_policy
+        .clone();
+
+    Ok(usage(&*lock_cache(app_handle).await?, policy))
+}
+
+#[tauri::command]
+pub async fn list_cached_firmware(app_handle: AppHandle) -> Result<StorageUsage, StorageError> {
+    current_usage(&app_handle).await
+}
+
+/// Removes one version from the cache, pinned or not, or every unpinned entry when no
+/// version is given.
+#[tauri::command]
+pub async fn purge_cached_firmware(
+    app_handle: AppHandle,
+    version: Option<String>,
+) -> Result<StorageUsage, StorageError> {
+    let version = version
+        .map(|version| version.parse::<M8Version>())
+        .transpose()?;
+
+    let removed = lock_cache(&app_handle)
+        .await?
+        .remove_where(|entry| match &version {
+            Some(version) => entry.version.as_ref() == Some(version),
+            None => !entry.pinned,
+        })?;
+
+    if let (Some(version), true) = (&version, removed.is_empty()) {
+        return Err(StorageError::NotCached(version.to_string()));
+    }
+
+    current_usage(&app_handle).await
+}
+
+#[tauri::command]
+pub async fn pin_cached_firmware(
+    app_handle: AppHandle,
+    version: String,
+    pinned: bool,
+) -> Result<StorageUsage, StorageError> {
+    let version = version.parse::<M8Version>()?;
+
+    let changed = lock_cache(&app_handle)
+        .await?
+        .set_pinned(&version, pinned)?;
+
+    if changed == 0 {
+        return Err(StorageError::NotCached(version.to_string()));
+    }
+
+    current_usage(&app_handle).await
+}
+
+#[tauri::command]
+pub async fn set_cache_policy(
+    app_handle: AppHandle,
+    policy: EvictionPolicy,
+) -> Result<StorageUsage, StorageError> {
+    let store = app_handle
+        .store(SETTINGS_STORE)
+        .map_err(|error| StorageError::Store(error.to_string()))?;
+
+    store.set(
+        POLICY_KEY,
+        serde_json::to_value(&policy).map_err(CacheError::from)?,
+    );
+    store
+        .save()
+        .map_err(|error| StorageError::Store(error.to_string()))?;
+
+    app_handle.state::<AppState>().lock().await.cache_policy = policy;
+
+    enforce_policy(&app_handle).await?;
+
+    current_usage(&app_handle).await
+}
@@ -0,0 +1,382 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::firmware::{
+    board,
+    intel_hex::{self, HexImage},
+    version::M8Version,
+    ConnectedDevice, DeviceType,
+};
+
+const MODEL_PATTERN: &str = r"(?i)_MODEL(?<model>\d+)";
+
+static MODEL_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(MODEL_PATTERN).unwrap());
+
+const HEADLESS_MARKER: &str = "HEADLESS";
+
+#[derive(Debug, thiserror::Error, Eq, PartialEq)]
+pub enum VariantError {
+    #[error("Unable to determine the target model of {0}")]
+    UnrecognizedName(String),
+    #[error("{0} names more than one target model")]
+    ConflictingModels(String),
+    #[error("{name} targets unknown model MODEL{model}")]
+    UnknownModel { model: String, name: String },
+    #[error("Archive contains more than one {model:?} image: {names}")]
+    Ambiguous { model: DeviceType, names: String },
+    #[error("Archive mixes firmware versions: {0}")]
+    MixedVersions(String),
+    #[error("No {model:?} variant found for version {version}")]
+    NoVariant { model: DeviceType, version: String },
+    #[error("Device model {0:?} can't be flashed")]
+    UnsupportedDevice(DeviceType),
+}
+
+/// What a firmware image was built for, as parsed from its file name.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FirmwareVariant {
+    pub model: DeviceType,
+    pub version: Option<M8Version>,
+}
+
+impl FirmwareVariant {
+    pub fn beta(&self) -> Option<u32> {
+        self.version.as_ref().and_then(|version| version.beta)
+    }
+
+    pub fn revision(&self) -> Option<char> {
+        self.version.as_ref().and_then(|version| version.revision)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FirmwareImage {
+    pub path: PathBuf,
+    pub variant: FirmwareVariant,
+}
+
+/// A firmware image that has been checked against the board it will be flashed to.
+///
+/// Only [`select_for_device`] hands these out, so an upload can't be started with an
+/// image built for a different model.
+#[derive(Clone, Debug)]
+pub struct FlashTarget {
+    board_tag: String,
+    image: FirmwareImage,
+}
+
+impl FlashTarget {
+    pub fn board_tag(&self) -> &str {
+        &self.board_tag
+    }
+
+    pub fn image(&self) -> &FirmwareImage {
+        &self.image
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.image.path
+    }
+}
+
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// Classifies a `.hex` file by name, e.g. `M8_V6_2_0_BETA8A_MODEL02.hex`.
+///
+/// Before the introduction of MODEL:02, the firmware files did not specify the
+/// supported model in their filenames, so a versioned name without a model is MODEL:01.
+pub fn classify(path: &Path) -> Result<FirmwareVariant, VariantError> {
+    let name = display_name(path);
+
+    let version = M8Version::from_file_name(&name);
+
+    let mut models: Vec<DeviceType> = Vec::new();
+
+    for captures in MODEL_REGEX.captures_iter(&name) {
+        models.push(match captures["model"].parse::<u32>() {
+            Ok(1) => DeviceType::MODEL01,
+            Ok(2) => DeviceType::MODEL02,
+            _ => {
+                return Err(VariantError::UnknownModel {
+                    model: captures["model"].to_owned(),
+                    name,
+                })
+            }
+        });
+    }
+
+    if name.to_ascii_uppercase().contains(HEADLESS_MARKER) {
+        models.push(DeviceType::HEADLESS);
+    }
+
+    models.dedup();
+
+    let model = match models.as_slice() {
+        [model] => model.clone(),
+        [] if version.is_some() => DeviceType::MODEL01,
+        [] => return Err(VariantError::UnrecognizedName(name)),
+        _ => return Err(VariantError::ConflictingModels(name)),
+    };
+
+    Ok(FirmwareVariant { model, version })
+}
+
+/// Classifies a hex by name, falling back to its contents for custom or renamed
+/// firmware whose name doesn't identify the model or version.
+pub fn classify_image(path: &Path) -> Result<FirmwareVariant, VariantError> {
+    let image = intel_hex::read(path)
+        .inspect_err(|error| log::warn!("Unable to inspect {:?}: {}", path, error))
+        .ok();
+
+    classify_parsed(path, image.as_ref())
+}
+
+/// Like [`classify_image`], for a hex that has already been parsed.
+pub fn classify_parsed(
+    path: &Path,
+    image: Option<&HexImage>,
+) -> Result<FirmwareVariant, VariantError> {
+    let named = classify(path);
+
+    if let Ok(FirmwareVariant {
+        version: Some(_), ..
+    }) = named
+    {
+        return named;
+    }
+
+    let version = image.and_then(HexImage::embedded_version);
+
+    match named {
+        Ok(variant) => Ok(FirmwareVariant { version, ..variant }),
+        Err(VariantError::UnrecognizedName(name)) => image
+            .and_then(|image| board::detect(image).board)
+            .map(|board| FirmwareVariant {
+                model: board.device_type(),
+                version,
+            })
+            .ok_or(VariantError::UnrecognizedName(name)),
+        Err(error) => Err(error),
+    }
+}
+
+/// Classifies every hex extracted from an archive, rejecting archives where the
+/// image to flash for a model would be ambiguous.
+pub fn classify_archive(paths: Vec<PathBuf>) -> Result<Vec<FirmwareImage>, VariantError> {
+    let images = paths
+        .into_iter()
+        .map(|path| {
+            classify_image(&path).map(|variant| FirmwareImage {
+                path: path.clone(),
+                variant,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    check_images(&images)?;
+
+    Ok(images)
+}
+
+/// Rejects sets of images where the one to flash for a model would be ambiguous, or
+/// that mix firmware versions.
+pub fn check_images(images: &[FirmwareImage]) -> Result<(), VariantError> {
+    for model in [
+        DeviceType::MODEL01,
+        DeviceType::MODEL02,
+        DeviceType::HEADLESS,
+    ] {
+        let names: Vec<String> = images
+            .iter()
+            .filter(|image| image.variant.model == model)
+            .map(|image| display_name(&image.path))
+            .collect();
+
+        if names.len() > 1 {
+            return Err(VariantError::Ambiguous {
+                model,
+                names: names.join(", "),
+            });
+        }
+    }
+
+    let mut versions: Vec<&M8Version> = images
+        .iter()
+        .filter_map(|image| image.variant.version.as_ref())
+        .collect();
+
+    versions.sort();
+    versions.dedup();
+
+    if versions.len() > 1 {
+        return Err(VariantError::MixedVersions(
+            versions
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Picks the image built for `device`'s model.
+pub fn select_for_device(
+    images: Vec<FirmwareImage>,
+    device: &ConnectedDevice,
+    version: Option<&M8Version>,
+) -> Result<FlashTarget, VariantError> {
+    if device.device_type == DeviceType::UNKNOWN {
+        return Err(VariantError::UnsupportedDevice(device.device_type.clone()));
+    }
+
+    images
+        .into_iter()
+        .find(|image| image.variant.model == device.device_type)
+        .map(|image| FlashTarget {
+            board_tag: device.ty_cmd_info.tag.clone(),
+            image,
+        })
+        .ok_or_else(|| VariantError::NoVariant {
+            model: device.device_type.clone(),
+            version: version.map(ToString::to_string).unwrap_or_default(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{firmware::intel_hex::tests::hex_file, state::tests::sample_device};
+
+    fn device(device_type: DeviceType) -> ConnectedDevice {
+        ConnectedDevice {
+            device_type,
+            ..sample_device("123", "123-Teensy")
+        }
+    }
+
+    #[test]
+    fn classifies_file_names() {
+        let variant = classify(Path::new("cache/M8_V6_2_0_BETA8A_MODEL02.hex")).unwrap();
+        assert_eq!(variant.model, DeviceType::MODEL02);
+        assert_eq!(variant.beta(), Some(8));
+        assert_eq!(variant.revision(), Some('A'));
+
+        // Legacy names have no model and target MODEL:01
+        let legacy = classify(Path::new("M8_V2_7_8.hex")).unwrap();
+        assert_eq!(legacy.version, "2.7.8".parse().ok());
+        assert_eq!(legacy.model, DeviceType::MODEL01);
+
+        assert_eq!(
+            classify(Path::new("M8_V4_0_0_HEADLESS.hex")).unwrap().model,
+            DeviceType::HEADLESS
+        );
+
+        assert!(matches!(
+            classify(Path::new("firmware.hex")),
+            Err(VariantError::UnrecognizedName(_))
+        ));
+        assert!(matches!(
+            classify(Path::new("M8_V4_0_0_MODEL02_HEADLESS.hex")),
+            Err(VariantError::ConflictingModels(_))
+        ));
+        assert!(matches!(
+            classify(Path::new("M8_V4_0_0_MODEL03.hex")),
+            Err(VariantError::UnknownModel { .. })
+        ));
+    }
+
+    #[test]
+    fn classifies_renamed_images_by_contents() {
+        let mut data = vec![0u8; 0x1001];
+        data[..4].copy_from_slice(b"FCFB");
+        data[0x50..0x54].copy_from_slice(&0x0100_0000u32.to_le_bytes());
+        data[0x1000] = 0xD1;
+        data.extend_from_slice(b"\0M8 V4.0.1\0");
+
+        let path = std::env::temp_dir().join(format!("m8-custom-{}.hex", std::process::id()));
+        std::fs::write(&path, hex_file(0x6000_0000, &data)).unwrap();
+
+        let variant = classify_image(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let variant = variant.unwrap();
+        assert_eq!(variant.model, DeviceType::MODEL02);
+        assert_eq!(variant.version, "4.0.1".parse().ok());
+    }
+
+    #[test]
+    fn rejects_ambiguous_archives() {
+        let result = classify_archive(vec![
+            PathBuf::from("M8_V4_0_0.hex"),
+            PathBuf::from("M8_V4_0_0_MODEL01.hex"),
+        ]);
+
+        assert!(matches!(result, Err(VariantError::Ambiguous { .. })));
+
+        let result = classify_archive(vec![
+            PathBuf::from("M8_V4_0_0_MODEL01.hex"),
+            PathBuf::from("M8_V4_0_1_MODEL02.hex"),
+        ]);
+
+        assert!(matches!(result, Err(VariantError::MixedVersions(_))));
+    }
+
+    #[test]
+    fn selects_only_the_matching_model() {
+        let images = classify_archive(vec![
+            PathBuf::from("M8_V4_0_0_MODEL01.hex"),
+            PathBuf::from("M8_V4_0_0_MODEL02.hex"),
+        ])
+        .unwrap();
+
+        let target = select_for_device(images.clone(), &device(DeviceType::MODEL02), None).unwrap();
+        assert_eq!(target.path(), Path::new("M8_V4_0_0_MODEL02.hex"));
+        assert_eq!(target.board_tag(), "123-Teensy");
+
+        let model01_only = vec![images[0].clone()];
+        assert!(matches!(
+            select_for_device(model01_only, &device(DeviceType::MODEL02), None),
+            Err(VariantError::NoVariant { .. })
+        ));
+    }
+
+    #[test]
+    fn headless_boards_get_the_headless_image() {
+        let images = classify_archive(vec![
+            PathBuf::from("M8_V4_0_0_MODEL01.hex"),
+            PathBuf::from("M8_V4_0_0_MODEL02.hex"),
+            PathBuf::from("M8_V4_0_0_HEADLESS.hex"),
+        ])
+        .unwrap();
+
+        let target =
+            select_for_device(images.clone(), &device(DeviceType::HEADLESS), None).unwrap();
+        assert_eq!(target.path(), Path::new("M8_V4_0_0_HEADLESS.hex"));
+
+        // Never falls back to a MODEL:01 image, which is built for a smaller flash
+        assert!(matches!(
+            select_for_device(images[..2].to_vec(), &device(DeviceType::HEADLESS), None),
+            Err(VariantError::NoVariant {
+                model: DeviceType::HEADLESS,
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            select_for_device(images, &device(DeviceType::UNKNOWN), None),
+            Err(VariantError::UnsupportedDevice(DeviceType::UNKNOWN))
+        ));
+    }
+}
@@ -0,0 +1,284 @@
+use std::{cmp::Ordering, fmt, str::FromStr, sync::LazyLock};
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// Changelog form: `3.2.1`, `3.2.1A`, `6.2.0 Beta 8A`
+const VERSION_PATTERN: &str =
+    r"^(?<major>\d+)\.(?<minor>\d+)\.(?<patch>\d+)(?: Beta ?(?<beta>\d+))?(?<revision>[A-Z])?$";
+
+// File form: `M8_V6_2_0_BETA8A_MODEL02.hex`, `M8Firmware_V3_2_1A.zip`
+const FILE_NAME_PATTERN: &str = r"(?i)^M8(?:Firmware)?_V(?<major>\d+)_(?<minor>\d+)_(?<patch>\d+)(?:_BETA(?<beta>\d+))?(?<revision>[A-Z])?(?:_MODEL\d+|_HEADLESS)?(?:\.(?:hex|zip))?$";
+
+// Embedded form: `M8 V4.0.1`, `Version 6.2.0 Beta 8A`, `M8 Firmware v3.2.1A`
+const EMBEDDED_PATTERN: &str = r"(?i)(?:\bM8[ _-]*(?:firmware[ _-]*)?v?|\bversion[ :]*)(?<major>\d+)\.(?<minor>\d+)\.(?<patch>\d+)(?:[ _-]?beta[ _-]?(?<beta>\d+))?(?<revision>[A-Z])?\b";
+
+static VERSION_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(VERSION_PATTERN).unwrap());
+
+static FILE_NAME_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(FILE_NAME_PATTERN).unwrap());
+
+static EMBEDDED_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(EMBEDDED_PATTERN).unwrap());
+
+// Shorter runs of printable bytes in a firmware image are almost always noise
+const MIN_EMBEDDED_STRING_LENGTH: usize = 6;
+
+#[derive(Debug, thiserror::Error, Eq, PartialEq)]
+#[error("Unrecognized M8 firmware version: {0}")]
+pub struct VersionParseError(pub String);
+
+/// An M8 firmware version.
+///
+/// Betas sort before the release they lead up to, and a letter revision sorts after
+/// the version it patches: `6.2.0 Beta 8 < 6.2.0 Beta 8A < 6.2.0 < 6.2.0A`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct M8Version {
+    pub beta: Option<u32>,
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    pub revision: Option<char>,
+}
+
+impl M8Version {
+    pub fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            beta: None,
+            major,
+            minor,
+            patch,
+            revision: None,
+        }
+    }
+
+    fn from_captures(captures: regex::Captures) -> Self {
+        let number = |name: &str| captures[name].parse::<u32>().unwrap_or_default();
+
+        Self {
+            beta: captures
+                .name("beta")
+                .and_then(|beta| beta.as_str().parse().ok()),
+            major: number("major"),
+            minor: number("minor"),
+            patch: number("patch"),
+            revision: captures
+                .name("revision")
+                .and_then(|revision| revision.as_str().chars().next())
+                .map(|revision| revision.to_ascii_uppercase()),
+        }
+    }
+
+    /// Parses a hex or archive file name such as `M8_V6_2_0_BETA8A_MODEL02.hex` or
+    /// `M8Firmware_V3_2_1.zip`. The top-level `M8Firmware.zip` carries no version.
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        FILE_NAME_REGEX.captures(name).map(Self::from_captures)
+    }
+
+    /// Scans firmware bytes for the version string the build embeds. Returns `None` if
+    /// there is none, or if the image names more than one version.
+    pub fn find_embedded(data: &[u8]) -> Option<Self> {
+        let mut versions: Vec<Self> = data
+            .split(|byte| !(byte.is_ascii_graphic() || *byte == b' '))
+            .filter(|run| run.len() >= MIN_EMBEDDED_STRING_LENGTH)
+            .filter_map(|run| std::str::from_utf8(run).ok())
+            .flat_map(|text| {
+                EMBEDDED_REGEX
+                    .captures_iter(text)
+                    .map(Self::from_captures)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        versions.sort();
+        versions.dedup();
+
+        match versions.as_slice() {
+            [version] => Some(version.clone()),
+            [] => None,
+            _ => {
+                log::warn!("Image embeds several versions: {:?}", versions);
+
+                None
+            }
+        }
+    }
+
+    /// The version without its letter revision, which `3.2.1A` and `3.2.1B` share.
+    pub fn base(&self) -> Self {
+        Self {
+            revision: None,
+            ..self.clone()
+        }
+    }
+
+    /// A file name safe rendering, e.g. `6_2_0_BETA8A`.
+    pub fn file_stem(&self) -> String {
+        let mut stem = format!("{}_{}_{}", self.major, self.minor, self.patch);
+
+        if let Some(beta) = self.beta {
+            stem.push_str(&format!("_BETA{}", beta));
+        }
+
+        if let Some(revision) = self.revision {
+            stem.push(revision);
+        }
+
+        stem
+    }
+}
+
+impl Ord for M8Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.beta, other.beta) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) => a.cmp(&b),
+            })
+            .then_with(|| self.revision.cmp(&other.revision))
+    }
+}
+
+impl PartialOrd for M8Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FromStr for M8Version {
+    type Err = VersionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        VERSION_REGEX
+            .captures(s)
+            .map(Self::from_captures)
+            .or_else(|| Self::from_file_name(s))
+            .ok_or_else(|| VersionParseError(s.to_owned()))
+    }
+}
+
+impl fmt::Display for M8Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+
+        if let Some(beta) = self.beta {
+            write!(f, " Beta {}", beta)?;
+        }
+
+        if let Some(revision) = self.revision {
+            write!(f, "{}", revision)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Serialized in the changelog form, which is what the frontend displays
+impl Serialize for M8Version {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for M8Version {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> M8Version {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_changelog_versions() {
+        assert_eq!(v("3.2.1"), M8Version::new(3, 2, 1));
+        assert_eq!(v("3.2.1A").revision, Some('A'));
+
+        let beta = v("6.2.0 Beta 8A");
+        assert_eq!(beta.beta, Some(8));
+        assert_eq!(beta.revision, Some('A'));
+        assert_eq!(beta.to_string(), "6.2.0 Beta 8A");
+
+        assert!("3.2".parse::<M8Version>().is_err());
+    }
+
+    #[test]
+    fn parses_file_names() {
+        assert_eq!(
+            M8Version::from_file_name("M8_V6_2_0_BETA8A_MODEL02.hex"),
+            Some(v("6.2.0 Beta 8A"))
+        );
+        assert_eq!(
+            M8Version::from_file_name("M8_V3_2_1A.hex"),
+            Some(v("3.2.1A"))
+        );
+        assert_eq!(
+            M8Version::from_file_name("M8Firmware_V2_7_8.zip"),
+            Some(v("2.7.8"))
+        );
+        assert_eq!(M8Version::from_file_name("M8Firmware.zip"), None);
+        assert_eq!(v("6.2.0 Beta 8A").file_stem(), "6_2_0_BETA8A");
+    }
+
+    #[test]
+    fn finds_embedded_version() {
+        let data = b"\x00\x01M8 V6.2.0 BETA 8A\x00\xffUSB Serial\x00";
+        assert_eq!(M8Version::find_embedded(data), Some(v("6.2.0 Beta 8A")));
+
+        assert_eq!(
+            M8Version::find_embedded(b"\x00Version 4.0.1\x00"),
+            Some(v("4.0.1"))
+        );
+        assert_eq!(M8Version::find_embedded(b"\x001.2.3.4 build\x00"), None);
+        // A bare `v` prefix is as likely to be a library's version as the firmware's
+        assert_eq!(M8Version::find_embedded(b"\x00USB v1.2.3\x00"), None);
+        assert_eq!(
+            M8Version::find_embedded(b"\x00M8 v4.0.1\x00M8 v3.2.1\x00"),
+            None
+        );
+    }
+
+    #[test]
+    fn orders_numerically_with_betas_and_revisions() {
+        let mut versions = [
+            v("10.0.0"),
+            v("6.2.0A"),
+            v("6.2.0"),
+            v("6.2.0 Beta 10"),
+            v("6.2.0 Beta 8A"),
+            v("6.2.0 Beta 8"),
+            v("9.1.0"),
+        ];
+
+        versions.sort();
+
+        assert_eq!(
+            versions.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "6.2.0 Beta 8",
+                "6.2.0 Beta 8A",
+                "6.2.0 Beta 10",
+                "6.2.0",
+                "6.2.0A",
+                "9.1.0",
+                "10.0.0",
+            ]
+        );
+    }
+}
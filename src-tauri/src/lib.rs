@@ -2,8 +2,13 @@ use std::env;
 use std::sync::Arc;
 
 use anyhow::Result;
-use firmware::start_firmware_download_handler;
-use serial::provider::{DeviceProvider, TycmdProvider};
+use firmware::{
+    download::{self, DownloadManager},
+    github::{self, GitHubClient},
+    prefetch, sources, start_firmware_download_handler,
+    storage::{self, SharedCache},
+};
+use serial::provider;
 use tauri::{App, AppHandle, Emitter, Manager};
 
 use crate::{
@@ -31,7 +36,20 @@ fn setup(app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
             .unwrap()
     );
 
-    app_handle.manage(AppState::new(AppStateData::default()));
+    let mut state = AppStateData::default();
+    state.cache_policy = storage::load_policy(app_handle);
+    state.github_token = github::load_token(app_handle);
+    state.device_provider = provider::load_kind(app_handle);
+    state.prefetch_enabled = prefetch::load_enabled(app_handle);
+    state.sources = sources::load_sources(app_handle);
+
+    app_handle.manage(AppState::new(state));
+
+    app_handle.manage(DownloadManager::new(download::MAX_CONCURRENT_DOWNLOADS));
+
+    app_handle.manage(GitHubClient::new()?);
+
+    app_handle.manage(SharedCache::default());
 
     let updater_app_handle = app_handle.clone();
 
@@ -46,6 +64,16 @@ fn setup(app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
 
     firmware::setup_firmware_store(app_handle)?;
 
+    let storage_app_handle = app_handle.clone();
+
+    tauri::async_runtime::spawn(async move {
+        if let Err(error) = storage::enforce_policy(&storage_app_handle).await {
+            log::warn!("Unable to trim firmware cache: {}", error);
+        }
+    });
+
+    prefetch::start(app_handle.clone());
+
     #[cfg(any(windows, target_os = "linux"))]
     {
         use tauri_plugin_deep_link::DeepLinkExt;
@@ -69,37 +97,15 @@ fn setup_event_listeners(app_handle: Arc<AppHandle>) {
         },
     );
 
-    let version_selected_app_handle = app_handle.clone();
-
-    frontend_events::VersionSelected::listen(
-        &version_selected_app_handle.clone(),
-        move |_event, payload| {
-            log::info!(
-                "User created: Version={:?}, DownloadLink={}",
-                payload.version,
-                payload.path
-            );
+    let cancel_firmware_download_app_handle = app_handle.clone();
 
-            let state_set_app_handle = version_selected_app_handle.clone();
-
-            tauri::async_runtime::spawn(async move {
-                use crate::firmware::ArchiveSource;
-                let state = state_set_app_handle.state::<AppState>();
-
-                let mut state_guard = state.lock().await;
-
-                // Determine archive source from payload.path
-                if payload.path.starts_with("http://") || payload.path.starts_with("https://") {
-                    state_guard.archive_source = ArchiveSource::RemoteUrl(payload.path.clone());
-                } else {
-                    state_guard.archive_source =
-                        ArchiveSource::LocalPath(std::path::PathBuf::from(payload.path.clone()));
-
-                    state_guard.size = 0;
-                }
-
-                state_guard.version = payload.version.clone().unwrap_or_default();
-            });
+    frontend_events::CancelFirmwareDownload::listen(
+        &cancel_firmware_download_app_handle.clone(),
+        move |_, _| {
+            log::info!("In cancelfirmwaredownload callback");
+            cancel_firmware_download_app_handle
+                .state::<DownloadManager>()
+                .cancel_all();
         },
     );
 
@@ -108,13 +114,11 @@ fn setup_event_listeners(app_handle: Arc<AppHandle>) {
     frontend_events::FrontendLoaded::listen(&app_handle.clone(), move |_event, _| {
         log::info!("Frontend has been loaded");
 
-        let provider = TycmdProvider;
-
-        provider.start(&app_handle.clone());
-
         let app_handle = app_handle.clone();
 
         tauri::async_runtime::spawn(async move {
+            provider::start(&app_handle).await;
+
             let state = app_handle.state::<AppState>();
             let mut state_guard = state.lock().await;
 
@@ -242,6 +246,32 @@ pub fn run() {
     // .run(tauri::generate_context!())
     // .expect("error while running tauri application");
 
+    builder = builder.invoke_handler(tauri::generate_handler![
+        firmware::batch::get_batch_status,
+        firmware::batch::start_batch_flash,
+        firmware::catalog::get_firmware_catalog,
+        firmware::catalog::select_version,
+        firmware::github::has_github_token,
+        firmware::github::set_github_token,
+        firmware::inspect::inspect_firmware,
+        firmware::prefetch::get_prefetch_enabled,
+        firmware::prefetch::set_prefetch_enabled,
+        firmware::recovery::dismiss_interrupted_flash,
+        firmware::recovery::get_interrupted_flash,
+        firmware::restore::plan_restore,
+        firmware::restore::restore_device,
+        firmware::sources::get_firmware_sources,
+        firmware::sources::set_firmware_sources,
+        firmware::storage::list_cached_firmware,
+        firmware::storage::pin_cached_firmware,
+        firmware::storage::purge_cached_firmware,
+        firmware::storage::set_cache_policy,
+        serial::device::select_device,
+        serial::provider::get_device_provider,
+        serial::provider::get_supported_providers,
+        serial::provider::set_device_provider
+    ]);
+
     if let Err(e) = builder.setup(setup).run(tauri::generate_context!()) {
         eprintln!("Error while running Tauri application: {e:?}");
         log::error!("Error while running Tauri application: {}", e);
@@ -40,6 +40,7 @@ use slip::{process_command, SlipDecoder};
 // 254 - Draw rectangle command: 12 bytes. int16 x position, int16 y position, int16 width, int16 height, uint8 r, uint8 g, uint8 b
 
 pub mod device;
+pub mod native;
 pub mod provider;
 pub mod slip;
 pub mod tycmd;
@@ -51,10 +52,13 @@ pub const DIRTYWAVE_VENDOR_ID: u16 = 5824;
 pub const MODEL_2_PRODUCT_ID: u16 = 1162;
 
 pub fn get_usb_port_info(port: &SerialPortInfo) -> Option<UsbPortInfo> {
-    if port.port_name.contains("cu") {
-        if let serialport::SerialPortType::UsbPort(ref usb_port) = port.port_type {
-            return Some(usb_port.clone());
-        }
+    // macOS lists each port twice; only the callout device can be opened without a carrier
+    if cfg!(target_os = "macos") && !port.port_name.contains("/cu.") {
+        return None;
+    }
+
+    if let serialport::SerialPortType::UsbPort(ref usb_port) = port.port_type {
+        return Some(usb_port.clone());
     }
 
     None
@@ -80,10 +84,10 @@ pub fn filter_to_m8_serial_ports(ports: Vec<SerialPortInfo>) -> Vec<SerialPortIn
     ports.into_iter().filter(is_m8_serial_port).collect()
 }
 
-pub fn enumerate_m8_serial_ports() -> Vec<SerialPortInfo> {
-    let ports = serialport::available_ports().expect("No ports found!");
+pub fn enumerate_m8_serial_ports() -> serialport::Result<Vec<SerialPortInfo>> {
+    let ports = serialport::available_ports()?;
 
-    filter_to_m8_serial_ports(ports)
+    Ok(filter_to_m8_serial_ports(ports))
 }
 
 pub fn get_m8_details(path: &str) {
@@ -1,10 +1,36 @@
 use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
 
 use crate::{
     events::frontend_events::{DownloadStatus, UploadStatus},
-    firmware::ConnectedDevice,
+    firmware::{ConnectedDevice, ConnectedDeviceList},
+    serial::provider::ProviderKind,
+    state::AppState,
 };
 
+#[derive(Debug, thiserror::Error)]
+pub enum DeviceError {
+    #[error("No connected device has the tag {0}")]
+    Unknown(String),
+    #[error("The target device can't change while flashing")]
+    Busy,
+    #[error("The device provider can't change while flashing")]
+    ProviderBusy,
+    #[error("Unable to save the device provider: {0}")]
+    Store(String),
+    #[error("{0:?} can't find devices on this platform")]
+    UnsupportedProvider(ProviderKind),
+}
+
+impl Serialize for DeviceError {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_str())
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(tag = "kind")]
 pub enum DeviceState {
@@ -28,5 +54,24 @@ pub enum DeviceState {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DeviceStateUpdatePayload {
+    // Every connected board, ordered by tag
+    pub devices: ConnectedDeviceList,
+    // Set while the firmware catalog is served from disk because GitHub is unreachable
+    pub offline: bool,
+    pub selected_device: Option<String>,
     pub state: DeviceState,
 }
+
+/// Makes the board with `tag` the one flashes go to.
+#[tauri::command]
+pub async fn select_device(app_handle: AppHandle, tag: String) -> Result<(), DeviceError> {
+    let state = app_handle.state::<AppState>();
+
+    let mut state_guard = state.lock().await;
+
+    state_guard.select_device(&tag)?;
+
+    let _ = state_guard.emit_device_state_update(&app_handle);
+
+    Ok(())
+}
@@ -0,0 +1,254 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use serialport::SerialPortInfo;
+use tauri::AppHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::serial::{
+    enumerate_m8_serial_ports, get_usb_port_info,
+    provider::{report_entry, DeviceProvider},
+    tycmd::TyCmdListEntry,
+    PRODUCT_NAME,
+};
+
+// Often enough that a board shows up about as quickly as it does through tycmd
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Finds M8s among the USB serial ports by vendor and product id, polling for boards
+/// being plugged in and out. Needs no sidecar, but only knows the model on Linux.
+#[derive(Default)]
+pub struct SerialPortProvider;
+
+/// Where a port's board sits on the bus, read from its USB device.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UsbDevice {
+    pub location: String,
+    // bcdDevice
+    pub release: u16,
+}
+
+/// The Teensy a board is built on, which Teensyduino reports as the device release.
+fn model_for_release(release: u16) -> Option<&'static str> {
+    match release {
+        0x0279 => Some("Teensy 4.0"),
+        0x0280 => Some("Teensy 4.1"),
+        0x0281 => Some("Teensy MicroMod"),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn usb_device(port_name: &str) -> Option<UsbDevice> {
+    use std::{fs, path::Path};
+
+    let name = Path::new(port_name).file_name()?;
+
+    // The tty belongs to an interface of the USB device, so its parent holds the descriptor
+    let interface = fs::canonicalize(Path::new("/sys/class/tty").join(name).join("device")).ok()?;
+    let device = interface.parent()?;
+
+    let release = fs::read_to_string(device.join("bcdDevice")).ok()?;
+
+    Some(UsbDevice {
+        location: format!("usb-{}", device.file_name()?.to_string_lossy()),
+        release: u16::from_str_radix(release.trim(), 16).ok()?,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn usb_device(_port_name: &str) -> Option<UsbDevice> {
+    None
+}
+
+/// The boards behind `ports`, keyed by tag and shaped the way tycmd lists them.
+fn board_entries(
+    ports: Vec<SerialPortInfo>,
+    usb_device: impl Fn(&str) -> Option<UsbDevice>,
+) -> BTreeMap<String, TyCmdListEntry> {
+    let mut boards: BTreeMap<String, TyCmdListEntry> = BTreeMap::new();
+
+    for port in ports {
+        let Some(usb) = get_usb_port_info(&port) else {
+            continue;
+        };
+
+        let Some(serial) = usb.serial_number else {
+            log::info!("Skipping {} without a serial number", port.port_name);
+
+            continue;
+        };
+
+        let tag = format!("{}-Teensy", serial);
+
+        let interface = vec!["Serial".to_string(), port.port_name.clone()];
+
+        if let Some(board) = boards.get_mut(&tag) {
+            board.interfaces.push(interface);
+
+            continue;
+        }
+
+        let device = usb_device(&port.port_name);
+
+        boards.insert(
+            tag.clone(),
+            TyCmdListEntry {
+                action: "add".into(),
+                capabilities: vec!["serial".into()],
+                description: usb.product.unwrap_or_else(|| PRODUCT_NAME.into()),
+                interfaces: vec![interface],
+                location: device
+                    .as_ref()
+                    .map(|device| device.location.clone())
+                    .unwrap_or_else(|| port.port_name.clone()),
+                model: device
+                    .and_then(|device| model_for_release(device.release))
+                    .unwrap_or_default()
+                    .into(),
+                serial,
+                tag,
+            },
+        );
+    }
+
+    boards
+}
+
+/// The tycmd-style events that turn `previous` into `current`.
+fn changes(
+    previous: &BTreeMap<String, TyCmdListEntry>,
+    current: &BTreeMap<String, TyCmdListEntry>,
+) -> Vec<TyCmdListEntry> {
+    let removed = previous
+        .iter()
+        .filter(|(tag, _)| !current.contains_key(*tag))
+        .map(|(_, board)| TyCmdListEntry {
+            action: "remove".into(),
+            ..board.clone()
+        });
+
+    let updated = current
+        .iter()
+        .filter_map(|(tag, board)| match previous.get(tag) {
+            None => Some(board.clone()),
+            Some(seen) if seen != board => Some(TyCmdListEntry {
+                action: "change".into(),
+                ..board.clone()
+            }),
+            Some(_) => None,
+        });
+
+    removed.chain(updated).collect()
+}
+
+impl DeviceProvider for SerialPortProvider {
+    fn start(&self, app_handle: &AppHandle, stop: CancellationToken) {
+        let app_handle = app_handle.clone();
+
+        tauri::async_runtime::spawn(async move {
+            let mut boards = BTreeMap::new();
+
+            loop {
+                match tauri::async_runtime::spawn_blocking(enumerate_m8_serial_ports).await {
+                    Ok(Ok(ports)) => {
+                        let current = board_entries(ports, usb_device);
+
+                        for entry in changes(&boards, &current) {
+                            if stop.is_cancelled() {
+                                return;
+                            }
+
+                            report_entry(&app_handle, entry).await;
+                        }
+
+                        boards = current;
+                    }
+                    Ok(Err(error)) => log::warn!("Unable to list serial ports: {}", error),
+                    Err(error) => log::warn!("Unable to list serial ports: {}", error),
+                }
+
+                tokio::select! {
+                    _ = stop.cancelled() => break,
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+
+            log::info!("Stopped watching serial ports");
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::{DIRTYWAVE_VENDOR_ID, MODEL_2_PRODUCT_ID};
+    use serialport::{SerialPortType, UsbPortInfo};
+
+    fn port(name: &str, serial: &str) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: name.into(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                manufacturer: Some("DirtyWave".into()),
+                pid: MODEL_2_PRODUCT_ID,
+                product: Some("M8".into()),
+                serial_number: Some(serial.into()),
+                vid: DIRTYWAVE_VENDOR_ID,
+            }),
+        }
+    }
+
+    fn micromod(_: &str) -> Option<UsbDevice> {
+        Some(UsbDevice {
+            location: "usb-1-1".into(),
+            release: 0x0281,
+        })
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn reports_boards_coming_and_going() {
+        let first = board_entries(vec![port("/dev/ttyACM0", "14908930")], micromod);
+
+        let board = &first["14908930-Teensy"];
+        assert_eq!(board.description, "M8");
+        assert_eq!(board.location, "usb-1-1");
+        assert_eq!(board.model, "Teensy MicroMod");
+        assert_eq!(
+            board.interfaces,
+            vec![vec!["Serial".to_string(), "/dev/ttyACM0".to_string()]]
+        );
+
+        let added = changes(&BTreeMap::new(), &first);
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].action, "add");
+        assert!(changes(&first, &first).is_empty());
+
+        // The same board on another port, and a second one plugged in
+        let second = board_entries(
+            vec![
+                port("/dev/ttyACM1", "14908930"),
+                port("/dev/ttyACM2", "15000000"),
+            ],
+            micromod,
+        );
+
+        let actions: Vec<_> = changes(&first, &second)
+            .into_iter()
+            .map(|entry| (entry.action, entry.serial))
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                ("change".to_string(), "14908930".to_string()),
+                ("add".to_string(), "15000000".to_string()),
+            ]
+        );
+
+        let removed = changes(&second, &BTreeMap::new());
+        assert!(removed.iter().all(|entry| entry.action == "remove"));
+        assert_eq!(removed.len(), 2);
+
+        assert_eq!(model_for_release(0x0279), Some("Teensy 4.0"));
+        assert_eq!(model_for_release(0x0100), None);
+    }
+}
@@ -1,12 +1,26 @@
 use crate::{
     events::frontend_events::UploadStatus,
-    serial::tycmd::{tycmd_list, tycmd_watch},
+    firmware::{
+        determine_device_type, sources::SETTINGS_STORE, ConnectedDevice, DeviceMode, DeviceType,
+    },
+    serial::{
+        device::DeviceError,
+        native::SerialPortProvider,
+        tycmd::{tycmd_list, tycmd_watch, TyCmdListEntry},
+    },
+    state::AppState,
 };
 use async_trait::async_trait;
-use tauri::AppHandle;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tauri_plugin_store::StoreExt;
+use tokio_util::sync::CancellationToken;
+
+const PROVIDER_KEY: &str = "deviceProvider";
 
 pub trait DeviceProvider: Send + Sync {
-    fn start(&self, app_handle: &AppHandle);
+    /// Lists the connected boards, then watches for changes until `stop` is cancelled.
+    fn start(&self, app_handle: &AppHandle, stop: CancellationToken);
 }
 
 #[async_trait]
@@ -23,11 +37,168 @@ pub trait FirmwareUploader: Send + Sync {
 pub struct TycmdProvider;
 
 impl DeviceProvider for TycmdProvider {
-    fn start(&self, app_handle: &AppHandle) {
+    fn start(&self, app_handle: &AppHandle, stop: CancellationToken) {
         let handle = app_handle.clone();
         tauri::async_runtime::spawn(async move {
-            tycmd_list(&handle).await;
-            tycmd_watch(&handle).await;
+            tycmd_list(&handle, &stop).await;
+            tycmd_watch(&handle, &stop).await;
         });
     }
 }
+
+/// How connected boards are found.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ProviderKind {
+    // The bundled tycmd sidecar
+    #[default]
+    Tycmd,
+    // USB serial ports matched by vendor and product id. Only boards running firmware
+    // with a serial interface show up, so one in HalfKay or running Teensyduino RawHID
+    // is invisible and can't be restored without tycmd. Linux only, since the model is
+    // read from sysfs.
+    SerialPort,
+}
+
+impl ProviderKind {
+    pub fn is_supported(self) -> bool {
+        match self {
+            ProviderKind::Tycmd => true,
+            ProviderKind::SerialPort => cfg!(target_os = "linux"),
+        }
+    }
+
+    fn provider(self) -> Box<dyn DeviceProvider> {
+        match self {
+            ProviderKind::Tycmd => Box::new(TycmdProvider),
+            ProviderKind::SerialPort => Box::new(SerialPortProvider),
+        }
+    }
+}
+
+pub fn load_kind(app_handle: &AppHandle) -> ProviderKind {
+    app_handle
+        .store(SETTINGS_STORE)
+        .ok()
+        .and_then(|store| store.get(PROVIDER_KEY))
+        .and_then(|value| serde_json::from_value(value).ok())
+        .filter(|kind: &ProviderKind| kind.is_supported())
+        .unwrap_or_default()
+}
+
+/// Applies a board event from any provider.
+pub async fn report_entry(app_handle: &AppHandle, entry: TyCmdListEntry) {
+    let device_type = determine_device_type(&entry);
+    log::info!("Device type is {:?}", device_type);
+
+    if device_type == DeviceType::UNKNOWN {
+        log::info!("Skipping unknown device");
+
+        return;
+    }
+
+    let state = app_handle.state::<AppState>();
+
+    let mut state_guard = state.lock().await;
+
+    let device = ConnectedDevice {
+        action_history: vec![entry.action.to_string()],
+        device_type,
+        mode: DeviceMode::of(&entry),
+        ty_cmd_info: entry,
+        updated_at: chrono::Utc::now().timestamp_millis(),
+    };
+
+    log::info!("Valid device found: {:?}", device);
+
+    state_guard.track_device(device);
+
+    state_guard.emit_device_state_update(app_handle).ok();
+}
+
+/// Starts the chosen provider, stopping the one running before it. Boards it saw are
+/// forgotten, since the new provider lists them again.
+pub async fn start(app_handle: &AppHandle) {
+    let state = app_handle.state::<AppState>();
+
+    let mut state_guard = state.lock().await;
+
+    if let Some(stop) = state_guard.provider_stop.take() {
+        stop.cancel();
+
+        state_guard.devices.clear();
+    }
+
+    let stop = CancellationToken::new();
+
+    state_guard.provider_stop = Some(stop.clone());
+
+    let kind = state_guard.device_provider;
+
+    let _ = state_guard.emit_device_state_update(app_handle);
+
+    drop(state_guard);
+
+    log::info!("Finding devices with {:?}", kind);
+
+    kind.provider().start(app_handle, stop);
+}
+
+#[tauri::command]
+pub async fn get_device_provider(app_handle: AppHandle) -> ProviderKind {
+    let state = app_handle.state::<AppState>();
+
+    let kind = state.lock().await.device_provider;
+
+    kind
+}
+
+/// The providers that can find devices on this platform.
+#[tauri::command]
+pub fn get_supported_providers() -> Vec<ProviderKind> {
+    [ProviderKind::Tycmd, ProviderKind::SerialPort]
+        .into_iter()
+        .filter(|kind| kind.is_supported())
+        .collect()
+}
+
+#[tauri::command]
+pub async fn set_device_provider(
+    app_handle: AppHandle,
+    kind: ProviderKind,
+) -> Result<(), DeviceError> {
+    if !kind.is_supported() {
+        return Err(DeviceError::UnsupportedProvider(kind));
+    }
+
+    let state = app_handle.state::<AppState>();
+
+    let mut state_guard = state.lock().await;
+
+    if state_guard.is_flashing() {
+        return Err(DeviceError::ProviderBusy);
+    }
+
+    let store = app_handle
+        .store(SETTINGS_STORE)
+        .map_err(|error| DeviceError::Store(error.to_string()))?;
+
+    store.set(
+        PROVIDER_KEY,
+        serde_json::to_value(kind).map_err(|error| DeviceError::Store(error.to_string()))?,
+    );
+    store
+        .save()
+        .map_err(|error| DeviceError::Store(error.to_string()))?;
+
+    let changed = state_guard.device_provider != kind;
+
+    state_guard.device_provider = kind;
+
+    drop(state_guard);
+
+    if changed {
+        start(&app_handle).await;
+    }
+
+    Ok(())
+}
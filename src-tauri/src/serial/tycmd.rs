@@ -2,14 +2,13 @@ use std::future::Future;
 use std::pin::Pin;
 
 use serde::{Deserialize, Serialize};
-use tauri::{AppHandle, Manager};
+use tauri::AppHandle;
 use tauri_plugin_shell::process::CommandEvent;
 use tauri_plugin_shell::ShellExt;
+use tokio_util::sync::CancellationToken;
 
 use crate::events::frontend_events::UploadStatus;
-use crate::firmware::{determine_device_type, ConnectedDevice, DeviceType};
-use crate::serial::provider::FirmwareUploader;
-use crate::state::AppState;
+use crate::serial::provider::{report_entry, FirmwareUploader};
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct TyCmdListEntry {
@@ -99,63 +98,7 @@ pub fn process_tycmd_list_entry(
 
             // Process parsed entries
             for entry in entries {
-                let device_type = determine_device_type(&entry);
-                log::info!("Device type is {:?}", device_type);
-
-                if device_type == DeviceType::HEADLESS || device_type == DeviceType::UNKNOWN {
-                    log::info!("Skipping headless/unknown device");
-
-                    continue;
-                }
-
-                let state = app_handle.state::<AppState>();
-
-                let mut state_guard = state.lock().await;
-
-                let mut device = ConnectedDevice {
-                    action_history: vec![entry.action.to_string()],
-                    device_type,
-                    ty_cmd_info: entry,
-                    updated_at: chrono::Utc::now().timestamp_millis(),
-                };
-
-                log::info!("Valid device found: {:?}", device);
-
-                match device.ty_cmd_info.action.as_str() {
-                    "add" => {
-                        log::info!("action is add. Setting device");
-
-                        state_guard.device = Some(device);
-                    }
-                    "change" | "miss" => {
-                        log::info!("action is change or miss");
-
-                        if let Some(existing) = &state_guard.device {
-                            let mut history = existing.action_history.clone();
-
-                            history.push(device.ty_cmd_info.action.to_string());
-
-                            const MAX_HISTORY: usize = 20;
-
-                            if history.len() > MAX_HISTORY {
-                                let overflow = history.len() - MAX_HISTORY;
-
-                                history.drain(0..overflow);
-                            }
-
-                            device.action_history = history;
-                        }
-
-                        state_guard.device = Some(device);
-                    }
-                    "remove" => {
-                        log::info!("action is remove. Clearing device");
-                        state_guard.device = None;
-                    }
-                    _ => {}
-                }
-
-                state_guard.emit_device_state_update(&app_handle).ok();
+                report_entry(&app_handle, entry).await;
             }
         }
 
@@ -174,6 +117,7 @@ async fn invoke_tycmd(
     command: InvokeTyCmd,
     callback: fn(event: CommandEvent, app_handle: AppHandle, buffer: &mut String) -> BoxedFuture,
     app_handle: &AppHandle,
+    stop: &CancellationToken,
 ) {
     let invoke_app_handle = app_handle.clone();
 
@@ -189,11 +133,26 @@ async fn invoke_tycmd(
         });
 
     // TODO: Handle the failed expectation
-    let (mut rx, _child) = sidecar.spawn().expect("Failed to spawn tycmd");
+    let (mut rx, child) = sidecar.spawn().expect("Failed to spawn tycmd");
 
     let mut buffer = String::new();
 
-    while let Some(event) = rx.recv().await {
+    loop {
+        let event = tokio::select! {
+            event = rx.recv() => event,
+            _ = stop.cancelled() => {
+                if let Err(error) = child.kill() {
+                    log::warn!("Unable to stop tycmd: {}", error);
+                }
+
+                break;
+            }
+        };
+
+        let Some(event) = event else {
+            break;
+        };
+
         let result = callback(event, invoke_app_handle.clone(), &mut buffer).await;
 
         if command == InvokeTyCmd::List && result == Some(()) {
@@ -204,14 +163,27 @@ async fn invoke_tycmd(
     log::info!("Done receiving data from tycmd child process");
 }
 
-pub async fn tycmd_list(app_handle: &AppHandle) {
-    invoke_tycmd(InvokeTyCmd::List, process_tycmd_list_entry, app_handle).await;
+pub async fn tycmd_list(app_handle: &AppHandle, stop: &CancellationToken) {
+    invoke_tycmd(
+        InvokeTyCmd::List,
+        process_tycmd_list_entry,
+        app_handle,
+        stop,
+    )
+    .await;
 }
 
-pub async fn tycmd_watch(app_handle: &AppHandle) {
+pub async fn tycmd_watch(app_handle: &AppHandle, stop: &CancellationToken) {
     let app_handle = app_handle.clone();
+    let stop = stop.clone();
 
     tauri::async_runtime::spawn(async move {
-        invoke_tycmd(InvokeTyCmd::Watch, process_tycmd_list_entry, &app_handle).await;
+        invoke_tycmd(
+            InvokeTyCmd::Watch,
+            process_tycmd_list_entry,
+            &app_handle,
+            &stop,
+        )
+        .await;
     });
 }
@@ -3,27 +3,67 @@ use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 
 use tauri::{AppHandle, Emitter};
+use tokio_util::sync::CancellationToken;
 
-use crate::events::frontend_events::FlashingStatus;
-use crate::firmware::{ArchiveSource, ConnectedDevice};
-use crate::serial::device::{DeviceState, DeviceStateUpdatePayload};
+use crate::events::frontend_events::{DownloadState, FlashingStatus, UploadState};
+use crate::firmware::{
+    batch::BatchStatus, cache::EvictionPolicy, catalog::FirmwareCatalog,
+    integrity::ExpectedArchive, recovery::FlashJob, restore::PendingRestore, sources::SourceConfig,
+    version::M8Version, ArchiveSource, ConnectedDevice, ConnectedDeviceList,
+};
+use crate::serial::{
+    device::{DeviceError, DeviceState, DeviceStateUpdatePayload},
+    provider::ProviderKind,
+};
+
+// Actions remembered per board
+const MAX_ACTION_HISTORY: usize = 20;
 
 #[derive(Default)]
 pub struct AppStateData {
     pub archive_source: ArchiveSource,
+    // The latest batch flash, kept after it finishes for its summary
+    pub batch: Option<BatchStatus>,
     pub cache_dir: Option<Box<tauri_plugin_fs::FilePath>>,
-    pub device: Option<ConnectedDevice>,
+    pub cache_policy: EvictionPolicy,
+    pub catalog: Option<FirmwareCatalog>,
+    // Tag of a board in recovery the user agreed to restore
+    pub confirmed_restore: Option<String>,
+    pub device_provider: ProviderKind,
+    // Every connected board, keyed by tycmd tag
+    pub devices: HashMap<String, ConnectedDevice>,
+    pub expected_archive: Option<ExpectedArchive>,
     pub flashing: Option<FlashingStatus>,
+    pub github_token: Option<String>,
+    // A flash the previous run started but never finished
+    pub interrupted_flash: Option<FlashJob>,
     pub last_digest: Option<u64>,
+    last_emitted_offline: bool,
     last_emitted_state: Option<DeviceState>,
+    pub offline: bool,
+    // A restore shown to the user, awaiting their answer
+    pub pending_restore: Option<PendingRestore>,
+    pub prefetch_enabled: bool,
+    // Stops the running device provider
+    pub provider_stop: Option<CancellationToken>,
+    // Tag of the board flashes go to
+    pub selected_device: Option<String>,
     pub size: u64,
+    pub sources: SourceConfig,
     pub temp_dir: Option<Box<tauri_plugin_fs::FilePath>>,
-    pub version: String,
+    pub version: Option<M8Version>,
 }
 
 impl AppStateData {
+    /// The board flashes go to.
+    pub fn device(&self) -> Option<&ConnectedDevice> {
+        self.selected_device
+            .as_ref()
+            .and_then(|tag| self.devices.get(tag))
+    }
+
     pub fn consolidated_state(&self) -> DeviceState {
-        match (&self.device, &self.flashing) {
+        match (self.device(), &self.flashing) {
             (None, _) => DeviceState::Disconnected,
             (Some(device), Some(FlashingStatus::Downloading(status))) => DeviceState::Downloading {
                 device: device.clone(),
@@ -39,6 +79,80 @@ impl AppStateData {
         }
     }
 
+    /// Applies a tycmd event to the board it is about. A board that goes away stays
+    /// selected while it is being flashed, since it reappears after rebooting.
+    pub fn track_device(&mut self, mut device: ConnectedDevice) {
+        let tag = device.ty_cmd_info.tag.clone();
+
+        match device.ty_cmd_info.action.as_str() {
+            "add" => {
+                self.devices.insert(tag, device);
+            }
+            "change" | "miss" => {
+                if let Some(existing) = self.devices.get(&tag) {
+                    let mut history = existing.action_history.clone();
+
+                    history.push(device.ty_cmd_info.action.to_string());
+
+                    if history.len() > MAX_ACTION_HISTORY {
+                        let overflow = history.len() - MAX_ACTION_HISTORY;
+
+                        history.drain(0..overflow);
+                    }
+
+                    device.action_history = history;
+                }
+
+                self.devices.insert(tag, device);
+            }
+            "remove" => {
+                self.devices.remove(&tag);
+            }
+            _ => {}
+        }
+
+        if self.device().is_none() && !self.is_flashing() {
+            // Fall back to whichever board was seen last
+            self.selected_device = self
+                .devices
+                .values()
+                .max_by_key(|device| (device.updated_at, device.ty_cmd_info.tag.clone()))
+                .map(|device| device.ty_cmd_info.tag.clone());
+        }
+    }
+
+    pub fn select_device(&mut self, tag: &str) -> Result<(), DeviceError> {
+        if self.is_flashing() {
+            return Err(DeviceError::Busy);
+        }
+
+        if !self.devices.contains_key(tag) {
+            return Err(DeviceError::Unknown(tag.to_owned()));
+        }
+
+        self.selected_device = Some(tag.to_owned());
+
+        Ok(())
+    }
+
+    /// Whether a flash is under way, from its download through the end of the upload,
+    /// for a single board or a batch.
+    pub fn is_flashing(&self) -> bool {
+        if self.batch.as_ref().is_some_and(BatchStatus::is_running) {
+            return true;
+        }
+
+        match &self.flashing {
+            Some(FlashingStatus::Downloading(status)) => {
+                !matches!(status.state, DownloadState::Stopped | DownloadState::Error)
+            }
+            Some(FlashingStatus::Uploading(status)) => {
+                !matches!(status.state, UploadState::Stopped | UploadState::Error)
+            }
+            None => false,
+        }
+    }
+
     pub fn emit_device_state_update(&mut self, app_handle: &AppHandle) -> tauri::Result<()> {
         if let Some(payload) = self.take_device_state_update() {
             app_handle.emit_to("main", "device-state-update", payload)?;
@@ -51,10 +165,24 @@ impl AppStateData {
     pub fn take_device_state_update(&mut self) -> Option<DeviceStateUpdatePayload> {
         let consolidated = self.consolidated_state();
 
-        if self.last_emitted_state.as_ref() != Some(&consolidated) {
+        let digest = devices_digest(&self.devices);
+
+        if self.last_emitted_state.as_ref() != Some(&consolidated)
+            || self.last_emitted_offline != self.offline
+            || self.last_digest != Some(digest)
+        {
+            self.last_digest = Some(digest);
+            self.last_emitted_offline = self.offline;
             self.last_emitted_state = Some(consolidated.clone());
 
+            let mut devices: ConnectedDeviceList = self.devices.values().cloned().collect();
+
+            devices.sort_by(|a, b| a.ty_cmd_info.tag.cmp(&b.ty_cmd_info.tag));
+
             Some(DeviceStateUpdatePayload {
+                devices,
+                offline: self.offline,
+                selected_device: self.selected_device.clone(),
                 state: consolidated,
             })
         } else {
@@ -81,12 +209,12 @@ pub type AppState = tokio::sync::Mutex<AppStateData>;
 // }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
-    use crate::firmware::{ConnectedDevice, DeviceType};
+    use crate::firmware::{ConnectedDevice, DeviceMode, DeviceType};
     use crate::serial::tycmd::TyCmdListEntry;
 
-    fn sample_entry(serial: &str, tag: &str) -> TyCmdListEntry {
+    pub(crate) fn sample_entry(serial: &str, tag: &str) -> TyCmdListEntry {
         TyCmdListEntry {
             action: "add".into(),
             capabilities: vec!["serial".into(), "run".into()],
@@ -99,15 +227,93 @@ mod tests {
         }
     }
 
-    fn sample_device(serial: &str, tag: &str) -> ConnectedDevice {
+    /// A running Model:01 as tycmd lists it, for tests that need a connected board.
+    pub(crate) fn sample_device(serial: &str, tag: &str) -> ConnectedDevice {
         ConnectedDevice {
             action_history: vec!["add".into()],
             device_type: DeviceType::MODEL01,
+            mode: DeviceMode::Running,
             ty_cmd_info: sample_entry(serial, tag),
             updated_at: 0,
         }
     }
 
+    #[test]
+    fn offline_changes_are_emitted() {
+        let mut state = AppStateData::default();
+
+        assert!(state.take_device_state_update().is_some());
+        assert!(state.take_device_state_update().is_none());
+
+        state.offline = true;
+
+        let payload = state.take_device_state_update().unwrap();
+        assert!(payload.offline);
+        assert_eq!(payload.state, DeviceState::Disconnected);
+    }
+
+    #[test]
+    fn failed_flashes_are_not_in_progress() {
+        use crate::events::frontend_events::UploadStatus;
+
+        let mut state = AppStateData::default();
+        assert!(!state.is_flashing());
+
+        state.flashing = Some(FlashingStatus::Uploading(UploadStatus {
+            log: None,
+            state: UploadState::Uploading,
+        }));
+        assert!(state.is_flashing());
+
+        state.flashing = Some(FlashingStatus::Uploading(UploadStatus {
+            log: Some("upload@status Failed".into()),
+            state: UploadState::Error,
+        }));
+        assert!(!state.is_flashing());
+    }
+
+    #[test]
+    fn tracks_each_board_and_keeps_a_target() {
+        let event = |tag: &str, action: &str, updated_at: i64| ConnectedDevice {
+            action_history: vec![action.into()],
+            device_type: DeviceType::MODEL02,
+            mode: DeviceMode::Running,
+            ty_cmd_info: TyCmdListEntry {
+                action: action.into(),
+                ..sample_entry(tag, tag)
+            },
+            updated_at,
+        };
+
+        let mut state = AppStateData::default();
+
+        state.track_device(event("a", "add", 1));
+        state.track_device(event("b", "add", 2));
+        assert_eq!(state.devices.len(), 2);
+        assert_eq!(state.selected_device.as_deref(), Some("a"));
+
+        state.select_device("b").unwrap();
+        assert!(matches!(
+            state.select_device("c"),
+            Err(DeviceError::Unknown(_))
+        ));
+
+        // Removing the other board leaves the target alone
+        state.track_device(event("a", "remove", 3));
+        assert_eq!(state.device().unwrap().ty_cmd_info.tag, "b");
+
+        state.track_device(event("b", "miss", 4));
+        assert_eq!(state.device().unwrap().action_history, vec!["add", "miss"]);
+
+        state.track_device(event("a", "add", 5));
+        state.track_device(event("b", "remove", 6));
+        assert_eq!(state.selected_device.as_deref(), Some("a"));
+
+        let payload = state.take_device_state_update().unwrap();
+        assert_eq!(payload.devices.len(), 1);
+        assert_eq!(payload.selected_device.as_deref(), Some("a"));
+    }
+
     #[test]
     fn digest_changes_with_content() {
         let mut a: HashMap<String, ConnectedDevice> = HashMap::new();